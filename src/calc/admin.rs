@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, HashMap};
+use geo::algorithm::centroid::Centroid;
+use geo::algorithm::contains::Contains;
+use geo::algorithm::intersects::Intersects;
+use crate::stream::{GeoStream, PolyStream};
+use super::Totals;
+use super::output::multipolygon;
+
+/// Name of the residual area of each area with children, outside of all of its children
+pub const UNASSIGNED: &str = "unassigned";
+
+///
+/// A single feature of a bounds layer, prior to being resolved into an area
+///
+#[derive(Debug, Clone)]
+pub struct Feature {
+    pub id: Option<String>,
+    pub name: String,
+    pub parent: Option<String>,
+    pub geom: geo::MultiPolygon<f64>
+}
+
+///
+/// A single administrative area of one of the bounds layers
+///
+#[derive(Debug, Clone)]
+pub struct Area {
+    pub id: String,
+    pub name: String,
+
+    /// 1 for the areas of the first bounds layer, 2 for the second, ...
+    pub level: i64,
+
+    /// Index of the containing area of the previous bounds layer
+    pub parent: Option<usize>,
+
+    /// The remainder of the parent outside of all of its other children, without a geometry of its own
+    pub residual: bool,
+
+    pub geom: geo::MultiPolygon<f64>
+}
+
+///
+/// The areas of one or more nested bounds layers (ie ADM1, ADM2, ADM3)
+///
+/// Population is only attributed to the leaf areas, those without any children,
+/// with the results of all other areas being the sum of their children, so that
+/// the results of each level add up exactly to those of the level above
+///
+/// Each area with children is given a residual "unassigned" child, holding the population
+/// of the area outside of all of its children, so that none of it is lost from the sum
+///
+#[derive(Debug)]
+pub struct Areas {
+    pub areas: Vec<Area>,
+    children: Vec<Vec<usize>>
+}
+
+impl Areas {
+    ///
+    /// Read a comma separated list of line-delimited GeoJSON bounds files,
+    /// ordered from the top level (ie ADM1) down
+    ///
+    pub fn load(sources: &str) -> Result<Self, String> {
+        let mut layers: Vec<Vec<Feature>> = Vec::new();
+
+        for source in sources.split(',').map(|source| source.trim()) {
+            let mut features: Vec<Feature> = Vec::new();
+
+            for poly in PolyStream::new(GeoStream::new(Some(source.to_string())), Some(String::from("/tmp/master_error.log"))) {
+                let name = match poly.props.get("name") {
+                    Some(serde_json::Value::String(name)) if !name.is_empty() => name.to_string(),
+                    _ => continue
+                };
+
+                let geom = match multipolygon(geojson::Value::MultiPolygon(poly.geom)) {
+                    Ok(geom) => geom,
+                    Err(_) => continue
+                };
+
+                features.push(Feature {
+                    id: property(&poly.props, "id"),
+                    name: name,
+                    parent: property(&poly.props, "parent"),
+                    geom: geom
+                });
+            }
+
+            if features.is_empty() {
+                return Err(format!("not ok - {} bounds features must have 'name' property to be included", source));
+            }
+
+            println!("ok - imported {} bounds from {}", features.len(), source);
+
+            layers.push(features);
+        }
+
+        Areas::new(layers)
+    }
+
+    ///
+    /// Resolve the features of each layer into areas. A feature is assigned to the area of
+    /// the previous layer given by its parent property, or that contains its centroid
+    ///
+    /// Without an id property, areas are identified by their name, prefixed by the id of
+    /// their parent. Features with the same id are merged into a single area
+    ///
+    pub fn new(layers: Vec<Vec<Feature>>) -> Result<Self, String> {
+        let mut areas: Vec<Area> = Vec::new();
+        let mut previous: HashMap<String, usize> = HashMap::new();
+
+        for (level, features) in layers.into_iter().enumerate() {
+            let level = level as i64 + 1;
+            let mut ids: HashMap<String, usize> = HashMap::new();
+
+            for feat in features {
+                let parent = if level == 1 {
+                    None
+                } else {
+                    match feat.parent {
+                        Some(ref parent) => match previous.get(parent) {
+                            Some(parent) => Some(*parent),
+                            None => { return Err(format!("{} bounds parent {} does not exist", &feat.name, parent)); }
+                        },
+                        None => match locate(&areas, &previous, &feat.geom) {
+                            Some(parent) => Some(parent),
+                            None => { return Err(format!("{} bounds is not within any bounds of the previous layer", &feat.name)); }
+                        }
+                    }
+                };
+
+                let id = match feat.id {
+                    Some(id) => id,
+                    None => match parent {
+                        None => feat.name.clone(),
+                        Some(parent) => format!("{}/{}", &areas[parent].id, &feat.name)
+                    }
+                };
+
+                match ids.get(&id) {
+                    Some(existing) => {
+                        if areas[*existing].parent != parent {
+                            return Err(format!("{} bounds id is within multiple parents", &id));
+                        }
+
+                        areas[*existing].geom.0.extend(feat.geom.0);
+                    },
+                    None => {
+                        ids.insert(id.clone(), areas.len());
+
+                        areas.push(Area {
+                            id: id,
+                            name: feat.name,
+                            level: level,
+                            parent: parent,
+                            residual: false,
+                            geom: feat.geom
+                        });
+                    }
+                };
+            }
+
+            previous = ids;
+        }
+
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); areas.len()];
+        for (i, area) in areas.iter().enumerate() {
+            if let Some(parent) = area.parent {
+                children[parent].push(i);
+            }
+        }
+
+        for parent in 0..children.len() {
+            if children[parent].is_empty() {
+                continue;
+            }
+
+            children[parent].push(areas.len());
+            children.push(Vec::new());
+
+            areas.push(Area {
+                id: format!("{}/{}", &areas[parent].id, UNASSIGNED),
+                name: String::from(UNASSIGNED),
+                level: areas[parent].level + 1,
+                parent: Some(parent),
+                residual: true,
+                geom: geo::MultiPolygon(Vec::new())
+            });
+        }
+
+        Ok(Areas {
+            areas: areas,
+            children: children
+        })
+    }
+
+    ///
+    /// Return the indexes of the areas without any children
+    ///
+    pub fn leaves(&self) -> Vec<usize> {
+        (0..self.areas.len()).filter(|i| self.children[*i].is_empty()).collect()
+    }
+
+    ///
+    /// Return the indexes of the children of an area, other than its residual area
+    ///
+    pub fn children(&self, i: usize) -> Vec<usize> {
+        self.children[i].iter().cloned().filter(|child| !self.areas[*child].residual).collect()
+    }
+
+    ///
+    /// Roll up the totals of each class of the leaf areas into every area, returning them
+    /// ordered depth first, with the network (all) totals of each area first
+    ///
+    /// Residual areas are only returned where they hold any population
+    ///
+    pub fn rollup(&self, leaves: Vec<(usize, Totals)>) -> Vec<(usize, Totals)> {
+        let mut sums: Vec<BTreeMap<String, Totals>> = vec![BTreeMap::new(); self.areas.len()];
+
+        for (leaf, totals) in leaves.iter() {
+            let mut current = Some(*leaf);
+
+            while let Some(i) = current {
+                sums[i].entry(totals.class.clone())
+                    .or_insert_with(|| self.totals(i, &totals.class))
+                    .sum(totals);
+
+                current = self.areas[i].parent;
+            }
+        }
+
+        let mut rolled: Vec<(usize, Totals)> = Vec::new();
+        let mut stack: Vec<usize> = (0..self.areas.len()).filter(|i| self.areas[*i].parent.is_none()).rev().collect();
+
+        while let Some(i) = stack.pop() {
+            if self.areas[i].residual && sums[i].is_empty() {
+                continue;
+            }
+
+            let network = sums[i].remove("all").unwrap_or_else(|| self.totals(i, "all"));
+
+            rolled.push((i, network.clone()));
+            for (_, totals) in std::mem::replace(&mut sums[i], BTreeMap::new()) {
+                rolled.push((i, totals.with_network(&network)));
+            }
+
+            stack.extend(self.children[i].iter().rev());
+        }
+
+        rolled
+    }
+
+    ///
+    /// Return empty totals of a given area & class
+    ///
+    pub fn totals(&self, i: usize, class: &str) -> Totals {
+        let area = &self.areas[i];
+
+        Totals::new(
+            &area.id,
+            &area.parent.map(|parent| self.areas[parent].id.clone()).unwrap_or_default(),
+            area.level,
+            &area.name,
+            class
+        )
+    }
+}
+
+///
+/// Return the index of the area of the previous layer containing the centroid of
+/// the given geometry, falling back to the first area that intersects it
+///
+fn locate(areas: &Vec<Area>, previous: &HashMap<String, usize>, geom: &geo::MultiPolygon<f64>) -> Option<usize> {
+    let mut candidates: Vec<usize> = previous.values().cloned().collect();
+    candidates.sort();
+
+    if let Some(centroid) = geom.centroid() {
+        if let Some(parent) = candidates.iter().find(|i| areas[**i].geom.contains(&centroid)) {
+            return Some(*parent);
+        }
+    }
+
+    candidates.into_iter().find(|i| {
+        areas[*i].geom.0.iter().any(|parent| geom.0.iter().any(|poly| parent.intersects(poly)))
+    })
+}
+
+///
+/// Return a string or numeric feature property as a string
+///
+fn property(props: &serde_json::Map<String, serde_json::Value>, key: &str) -> Option<String> {
+    match props.get(key) {
+        Some(serde_json::Value::String(value)) if !value.is_empty() => Some(value.to_string()),
+        Some(serde_json::Value::Number(value)) => Some(value.to_string()),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(minx: f64, miny: f64, size: f64) -> geo::MultiPolygon<f64> {
+        geo::MultiPolygon(vec![geo::Polygon::new(geo::LineString::from(vec![
+            (minx, miny),
+            (minx + size, miny),
+            (minx + size, miny + size),
+            (minx, miny + size),
+            (minx, miny)
+        ]), vec![])])
+    }
+
+    fn feature(id: Option<&str>, name: &str, parent: Option<&str>, geom: geo::MultiPolygon<f64>) -> Feature {
+        Feature {
+            id: id.map(String::from),
+            name: name.to_string(),
+            parent: parent.map(String::from),
+            geom: geom
+        }
+    }
+
+    fn areas() -> Areas {
+        Areas::new(vec![
+            vec![
+                feature(None, "Central", None, square(0.0, 0.0, 2.0)),
+                feature(Some("PY-1"), "Concepción", None, square(2.0, 0.0, 2.0))
+            ],
+            vec![
+                feature(None, "San Pedro", None, square(0.0, 0.0, 1.0)),
+                feature(None, "San Pedro", Some("PY-1"), square(2.0, 0.0, 1.0)),
+                feature(None, "San Pedro", Some("PY-1"), square(3.0, 0.0, 1.0))
+            ]
+        ]).unwrap()
+    }
+
+    fn leaf(class: &str, covered: f64, rural: f64) -> Totals {
+        let mut totals = Totals::new("", "", 0, "", class);
+        totals.covered = covered;
+        totals.uncovered = rural - covered;
+        totals.rural = rural;
+        totals.total = rural;
+        totals
+    }
+
+    #[test]
+    fn test_areas() {
+        let areas = areas();
+
+        let ids: Vec<(&str, i64, Option<usize>)> = areas.areas.iter().map(|area| {
+            (area.id.as_str(), area.level, area.parent)
+        }).collect();
+
+        assert_eq!(ids, vec![
+            ("Central", 1, None),
+            ("PY-1", 1, None),
+            ("Central/San Pedro", 2, Some(0)),
+            ("PY-1/San Pedro", 2, Some(1)),
+            ("Central/unassigned", 2, Some(0)),
+            ("PY-1/unassigned", 2, Some(1))
+        ]);
+
+        assert_eq!(areas.areas[3].geom.0.len(), 2);
+        assert_eq!(areas.leaves(), vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_residual() {
+        let areas = areas();
+
+        assert_eq!(areas.children(0), vec![2]);
+        assert!(areas.areas[4].residual);
+
+        // Population of Central outside of San Pedro is held by its residual area
+        let rolled = areas.rollup(vec![
+            (2, leaf("all", 1.0, 4.0)),
+            (3, leaf("all", 2.0, 6.0)),
+            (4, leaf("all", 0.5, 3.0))
+        ]);
+
+        let ids: Vec<&str> = rolled.iter().map(|(_, totals)| totals.id.as_str()).collect();
+        assert_eq!(ids, vec!["Central", "Central/San Pedro", "Central/unassigned", "PY-1", "PY-1/San Pedro"]);
+
+        // The children of each area add up exactly to the area
+        for (parent, totals) in rolled.iter().filter(|(i, _)| areas.areas[*i].parent.is_none()) {
+            let children: Vec<&Totals> = rolled.iter().filter(|(i, _)| areas.areas[*i].parent == Some(*parent)).map(|(_, totals)| totals).collect();
+
+            assert_eq!(children.iter().map(|child| child.rural).sum::<f64>(), totals.rural);
+            assert_eq!(children.iter().map(|child| child.covered).sum::<f64>(), totals.covered);
+        }
+    }
+
+    #[test]
+    fn test_invalid_areas() {
+        let err = Areas::new(vec![
+            vec![feature(None, "Central", None, square(0.0, 0.0, 1.0))],
+            vec![feature(None, "Asunción", None, square(5.0, 5.0, 1.0))]
+        ]).unwrap_err();
+        assert_eq!(err, "Asunción bounds is not within any bounds of the previous layer");
+
+        let err = Areas::new(vec![
+            vec![feature(None, "Central", None, square(0.0, 0.0, 1.0))],
+            vec![feature(None, "Asunción", Some("PY-11"), square(0.0, 0.0, 1.0))]
+        ]).unwrap_err();
+        assert_eq!(err, "Asunción bounds parent PY-11 does not exist");
+    }
+
+    #[test]
+    fn test_rollup() {
+        let areas = areas();
+
+        let rolled = areas.rollup(vec![
+            (2, leaf("all", 1.0, 4.0)),
+            (3, leaf("all", 2.0, 6.0)),
+            (3, leaf("primary", 1.0, 0.0))
+        ]);
+
+        let rows: Vec<(usize, String, String, f64, f64)> = rolled.into_iter().map(|(i, totals)| {
+            (i, totals.id, totals.class, totals.covered, totals.rural)
+        }).collect();
+
+        assert_eq!(rows, vec![
+            (0, String::from("Central"), String::from("all"), 1.0, 4.0),
+            (2, String::from("Central/San Pedro"), String::from("all"), 1.0, 4.0),
+            (1, String::from("PY-1"), String::from("all"), 2.0, 6.0),
+            (1, String::from("PY-1"), String::from("primary"), 1.0, 6.0),
+            (3, String::from("PY-1/San Pedro"), String::from("all"), 2.0, 6.0),
+            (3, String::from("PY-1/San Pedro"), String::from("primary"), 1.0, 6.0)
+        ]);
+    }
+}
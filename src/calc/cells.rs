@@ -0,0 +1,271 @@
+use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::intersects::Intersects;
+use super::admin::{Areas, Feature};
+use crate::grid::{LAT_METRES, LNG_METRES};
+
+/// Maximum number of cells generated over the extent of a country
+pub const MAX_CELLS: u64 = 1_000_000;
+
+///
+/// Shape of the cells of a generated grid
+///
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shape {
+    Hex,
+    Square
+}
+
+impl std::fmt::Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Shape::Hex => write!(f, "hex"),
+            Shape::Square => write!(f, "square"),
+        }
+    }
+}
+
+///
+/// A regular grid of hexagon or square cells, generated over a country as an
+/// alternative to administrative bounds
+///
+/// Cells are sized in metres at the centre latitude of the country, so cells are
+/// only approximately equal in size across countries spanning many degrees of latitude
+///
+#[derive(Debug, PartialEq)]
+pub struct Cells {
+    pub shape: Shape,
+
+    /// Width of a square, or the distance between the parallel sides of a hexagon (metres)
+    pub size: f64
+}
+
+impl Cells {
+    ///
+    /// Parse a grid given in the form SHAPE:SIZE (ie hex:5000)
+    ///
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let invalid = || format!("--grid {} must be in the form hex:SIZE or square:SIZE (metres)", input);
+
+        let mut parts = input.splitn(2, ':');
+
+        let shape = match parts.next().map(|shape| shape.trim().to_lowercase()) {
+            Some(ref shape) if shape == "hex" => Shape::Hex,
+            Some(ref shape) if shape == "square" => Shape::Square,
+            _ => { return Err(invalid()); }
+        };
+
+        let size: f64 = match parts.next().map(|size| size.trim().parse()) {
+            Some(Ok(size)) if size > 0.0 => size,
+            _ => { return Err(invalid()); }
+        };
+
+        Ok(Cells {
+            shape: shape,
+            size: size
+        })
+    }
+
+    ///
+    /// Return the cells of the grid that intersect the given country, as a single
+    /// layer of areas
+    ///
+    pub fn areas(&self, country: &geo::MultiPolygon<f64>) -> Result<Areas, String> {
+        let features = self.features(country)?;
+
+        if features.is_empty() {
+            return Err(String::from("--grid does not have any cells within the country"));
+        }
+
+        println!("ok - generated {} {} grid cells", features.len(), self.shape);
+
+        Areas::new(vec![features])
+    }
+
+    ///
+    /// Generate the cells of the grid over the extent of the country, keeping those
+    /// that intersect it. Cells are identified by their shape, column & row
+    ///
+    /// Grids of more than MAX_CELLS cells over the extent of the country are rejected
+    ///
+    pub fn features(&self, country: &geo::MultiPolygon<f64>) -> Result<Vec<Feature>, String> {
+        let extent = match country.bounding_rect() {
+            None => { return Ok(Vec::new()); },
+            Some(extent) => extent
+        };
+
+        let polys: Vec<(&geo::Polygon<f64>, Option<geo::Rect<f64>>)> = country.0.iter().map(|poly| {
+            (poly, poly.bounding_rect())
+        }).collect();
+
+        // Degrees per metre along each axis at the centre latitude of the country
+        let lat = (extent.min().y + extent.max().y) / 2.0;
+        let dx = 1.0 / (LNG_METRES * lat.to_radians().cos());
+        let dy = 1.0 / LAT_METRES;
+
+        let width = (extent.max().x - extent.min().x) / dx;
+        let height = (extent.max().y - extent.min().y) / dy;
+
+        let (cols, rows, row_spacing) = match self.shape {
+            Shape::Square => (
+                (width / self.size).ceil().max(1.0) as usize,
+                (height / self.size).ceil().max(1.0) as usize,
+                self.size
+            ),
+            Shape::Hex => {
+                let spacing = 1.5 * self.size / 3f64.sqrt();
+
+                (
+                    (width / self.size).ceil() as usize + 1,
+                    (height / spacing).ceil() as usize + 1,
+                    spacing
+                )
+            }
+        };
+
+        let count = cols as u64 * rows as u64;
+        if count > MAX_CELLS {
+            return Err(format!("--grid {}:{} would generate {} cells over the extent of the country, more than the limit of {}", self.shape, self.size, count, MAX_CELLS));
+        }
+
+        let mut features: Vec<Feature> = Vec::new();
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let ring: Vec<(f64, f64)> = self.ring(col, row, row_spacing).into_iter().map(|(x, y)| {
+                    (extent.min().x + x * dx, extent.min().y + y * dy)
+                }).collect();
+
+                let cell = geo::Polygon::new(geo::LineString::from(ring), vec![]);
+                let bbox = cell.bounding_rect().unwrap();
+
+                let within = polys.iter().any(|(poly, poly_bbox)| match poly_bbox {
+                    Some(poly_bbox) => poly_bbox.intersects(&bbox) && poly.intersects(&cell),
+                    None => false
+                });
+
+                if within {
+                    let id = format!("{}-{}-{}", self.shape, col, row);
+
+                    features.push(Feature {
+                        id: Some(id.clone()),
+                        name: id,
+                        parent: None,
+                        geom: geo::MultiPolygon(vec![cell])
+                    });
+                }
+            }
+        }
+
+        Ok(features)
+    }
+
+    ///
+    /// Return the closed ring of a cell, in metres from the minimum corner of the grid
+    ///
+    /// Hexagons are pointy topped, with every other row offset by half a cell
+    ///
+    fn ring(&self, col: usize, row: usize, row_spacing: f64) -> Vec<(f64, f64)> {
+        match self.shape {
+            Shape::Square => {
+                let (x, y) = (col as f64 * self.size, row as f64 * self.size);
+
+                vec![
+                    (x, y),
+                    (x + self.size, y),
+                    (x + self.size, y + self.size),
+                    (x, y + self.size),
+                    (x, y)
+                ]
+            },
+            Shape::Hex => {
+                let radius = self.size / 3f64.sqrt();
+                let offset = if row % 2 == 1 { self.size / 2.0 } else { 0.0 };
+                let (x, y) = (col as f64 * self.size + offset, row as f64 * row_spacing);
+
+                (0..=6).map(|corner| {
+                    let angle = (30.0 + 60.0 * (corner % 6) as f64).to_radians();
+                    (x + radius * angle.cos(), y + radius * angle.sin())
+                }).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn country() -> geo::MultiPolygon<f64> {
+        geo::MultiPolygon(vec![geo::Polygon::new(geo::LineString::from(vec![
+            (0.0, 0.0),
+            (0.1, 0.0),
+            (0.1, 0.1),
+            (0.0, 0.1),
+            (0.0, 0.0)
+        ]), vec![])])
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Cells::parse("hex:5000"), Ok(Cells {
+            shape: Shape::Hex,
+            size: 5000.0
+        }));
+
+        assert_eq!(Cells::parse("Square:2500.5"), Ok(Cells {
+            shape: Shape::Square,
+            size: 2500.5
+        }));
+
+        assert_eq!(Cells::parse("hex"), Err(String::from("--grid hex must be in the form hex:SIZE or square:SIZE (metres)")));
+        assert_eq!(Cells::parse("hex:-1"), Err(String::from("--grid hex:-1 must be in the form hex:SIZE or square:SIZE (metres)")));
+        assert_eq!(Cells::parse("h3:7"), Err(String::from("--grid h3:7 must be in the form hex:SIZE or square:SIZE (metres)")));
+    }
+
+    #[test]
+    fn test_square_cells() {
+        let cells = Cells::parse("square:5566").unwrap();
+        let features = cells.features(&country()).unwrap();
+
+        // 0.1 degrees is ~11132m of longitude & ~11057m of latitude at the equator
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[0].id, Some(String::from("square-0-0")));
+        assert_eq!(features[3].id, Some(String::from("square-1-1")));
+
+        let ring: Vec<(f64, f64)> = features[0].geom.0[0].exterior().points_iter().map(|pt| {
+            ((pt.x() * 1000.0).round() / 1000.0, (pt.y() * 1000.0).round() / 1000.0)
+        }).collect();
+
+        assert_eq!(ring, vec![(0.0, 0.0), (0.05, 0.0), (0.05, 0.05), (0.0, 0.05), (0.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_hex_cells() {
+        let cells = Cells::parse("hex:5000").unwrap();
+        let features = cells.features(&country()).unwrap();
+
+        assert!(!features.is_empty());
+        assert_eq!(features[0].id, Some(String::from("hex-0-0")));
+
+        // Every cell is a closed hexagon with a width of ~5000m
+        for feat in features.iter() {
+            let ring = feat.geom.0[0].exterior();
+            assert_eq!(ring.0.len(), 7);
+            assert_eq!(ring.0[0], ring.0[6]);
+
+            let bbox = feat.geom.bounding_rect().unwrap();
+            assert!(((bbox.max().x - bbox.min().x) * LNG_METRES - 5000.0).abs() < 1.0);
+        }
+
+        let areas = cells.areas(&country()).unwrap();
+        assert_eq!(areas.leaves().len(), features.len());
+    }
+
+    #[test]
+    fn test_too_many_cells() {
+        // 0.1 degrees is ~11132m, so 1m cells would be 11132 x 11058
+        let err = Cells::parse("square:1").unwrap().features(&country()).unwrap_err();
+
+        assert_eq!(err, "--grid square:1 would generate 123097656 cells over the extent of the country, more than the limit of 1000000");
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::Write;
+
+///
+/// A contiguous group of population pixels that are not fully covered by the network
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cluster {
+    pub id: i32,
+
+    /// Rural population of the cluster not covered by the network
+    pub population: f64,
+
+    /// Number of population pixels within the cluster
+    pub pixels: i64,
+
+    /// Distance (metres) from the cluster to the nearest road, None without any roads
+    pub distance: Option<f64>,
+
+    /// Point within the cluster, for display as a point layer
+    pub point: (f64, f64),
+
+    pub geom: serde_json::Value
+}
+
+impl Cluster {
+    ///
+    /// Return the cluster as a GeoJSON feature of its rank & polygon
+    ///
+    pub fn to_feature(&self, rank: usize) -> serde_json::Value {
+        serde_json::json!({
+            "type": "Feature",
+            "properties": {
+                "rank": rank,
+                "id": self.id,
+                "population": self.population,
+                "pixels": self.pixels,
+                "distance": self.distance,
+                "longitude": self.point.0,
+                "latitude": self.point.1
+            },
+            "geometry": self.geom
+        })
+    }
+}
+
+///
+/// Group the rural pixels that are not fully covered at the first access distance into
+/// contiguous clusters, writing them as line-delimited GeoJSON polygons, ranked by their
+/// uncovered population & the distance to the nearest road
+///
+/// Clusters are kept in the clusters table of the country, along with a point within each
+///
+pub fn report(db: &mut postgres::Client, iso: &String, buffer: i64, path: &str) {
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.clusters
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    // Pixels that share an edge or a corner are clustered together
+    db.execute(format!("
+        CREATE TABLE country_{iso}.clusters AS
+            SELECT
+                clusters.id::INT AS id,
+                clusters.pop,
+                clusters.pixels,
+                clusters.geom,
+                ST_PointOnSurface(clusters.geom) AS point,
+                road.distance
+            FROM
+                (
+                    SELECT
+                        uncovered.cluster AS id,
+                        SUM(uncovered.pop) AS pop,
+                        count(*) AS pixels,
+                        ST_Multi(ST_Union(uncovered.geom)) AS geom
+                    FROM
+                        (
+                            SELECT
+                                px.geom,
+                                px.pop * (100 - cov.coverage) * 0.01 AS pop,
+                                ST_ClusterDBSCAN(px.geom, 0, 1) OVER () AS cluster
+                            FROM
+                                country_{iso}.{iso}_geom AS px
+                                    INNER JOIN country_{iso}.{iso}_coverage AS cov
+                                        ON px.id = cov.id
+                            WHERE
+                                cov.buffer = $1
+                                AND cov.class IS NULL
+                                AND cov.coverage < 100
+                                AND NOT px.urban
+                        ) AS uncovered
+                    GROUP BY
+                        uncovered.cluster
+                ) AS clusters
+                    LEFT JOIN LATERAL (
+                        SELECT
+                            ST_Distance(clusters.geom::GEOGRAPHY, master.geom::GEOGRAPHY) AS distance
+                        FROM
+                            country_{iso}.master
+                        ORDER BY
+                            master.geom <-> clusters.geom
+                        LIMIT 1
+                    ) AS road ON TRUE
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    let clusters: Vec<Cluster> = match db.query(format!("
+        SELECT
+            id,
+            pop,
+            pixels,
+            distance,
+            ST_X(point),
+            ST_Y(point),
+            ST_AsGeoJSON(geom)::JSON
+        FROM
+            country_{iso}.clusters
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| Cluster {
+            id: row.get(0),
+            population: row.get(1),
+            pixels: row.get(2),
+            distance: row.get(3),
+            point: (row.get(4), row.get(5)),
+            geom: row.get(6)
+        }).collect()
+    };
+
+    let clusters = rank(clusters);
+
+    let mut output = std::io::BufWriter::new(std::fs::File::create(path).unwrap());
+    for (i, cluster) in clusters.iter().enumerate() {
+        writeln!(output, "{}", cluster.to_feature(i + 1)).unwrap();
+    }
+    output.flush().unwrap();
+
+    println!("ok - wrote {} uncovered clusters", clusters.len());
+}
+
+///
+/// Order clusters by their uncovered population, with clusters nearer to a road
+/// first where the population is equal
+///
+pub fn rank(mut clusters: Vec<Cluster>) -> Vec<Cluster> {
+    clusters.sort_by(|a, b| {
+        b.population.partial_cmp(&a.population).unwrap_or(std::cmp::Ordering::Equal).then_with(|| {
+            let a = a.distance.unwrap_or(std::f64::INFINITY);
+            let b = b.distance.unwrap_or(std::f64::INFINITY);
+
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        })
+    });
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cluster(id: i32, population: f64, distance: Option<f64>) -> Cluster {
+        Cluster {
+            id: id,
+            population: population,
+            pixels: 1,
+            distance: distance,
+            point: (0.5, 0.5),
+            geom: serde_json::json!({
+                "type": "MultiPolygon",
+                "coordinates": [[[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]]]
+            })
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let ranked: Vec<i32> = rank(vec![
+            cluster(1, 10.0, Some(500.0)),
+            cluster(2, 250.0, None),
+            cluster(3, 250.0, Some(1200.0)),
+            cluster(4, 40.0, Some(100.0))
+        ]).into_iter().map(|cluster| cluster.id).collect();
+
+        assert_eq!(ranked, vec![3, 2, 4, 1]);
+    }
+
+    #[test]
+    fn test_to_feature() {
+        assert_eq!(cluster(3, 250.0, Some(1200.0)).to_feature(1), serde_json::json!({
+            "type": "Feature",
+            "properties": {
+                "rank": 1,
+                "id": 3,
+                "population": 250.0,
+                "pixels": 1,
+                "distance": 1200.0,
+                "longitude": 0.5,
+                "latitude": 0.5
+            },
+            "geometry": {
+                "type": "MultiPolygon",
+                "coordinates": [[[[0, 0], [1, 0], [1, 1], [0, 1], [0, 0]]]]
+            }
+        }));
+    }
+}
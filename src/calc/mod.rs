@@ -0,0 +1,1508 @@
+use crate::pg::{Table, InputTable, Network, Country, Polygon, Population};
+use indicatif::ProgressBar;
+use crate::stream::{GeoStream, NetStream, PolyStream};
+use rayon::prelude::*;
+use std::thread;
+use std::collections::HashMap;
+
+pub mod rules;
+pub mod travel;
+pub mod native;
+pub mod stages;
+pub mod output;
+pub mod admin;
+pub mod sensitivity;
+pub mod runs;
+pub mod cells;
+pub mod clusters;
+pub mod proposals;
+pub mod season;
+pub use rules::Rules;
+use stages::{Stages, Status, fingerprint, file_fingerprint};
+use output::Output;
+use admin::Areas;
+use cells::Cells;
+use runs::{Runs, Timings};
+
+pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, args: &clap_v3::ArgMatches) {
+    let mut timings = Timings::new();
+
+    let iso = args.value_of("iso").unwrap().to_string().to_lowercase();
+    println!("ok - processing {}", &iso);
+
+    let master_src = args.value_of("NETWORK").unwrap().to_string();
+    let output = args.value_of("output").unwrap().to_string();
+
+    let buffers = buffers(args.value_of("buffer"));
+
+    let rules: Option<Rules> = match args.value_of("rules") {
+        None => None,
+        Some(rules) => match Rules::from_file(rules) {
+            Ok(rules) => Some(rules),
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    let mode = args.value_of("mode").unwrap_or("buffer").to_string();
+    let friction = match args.value_of("friction").map(travel::table) {
+        None => None,
+        Some(Ok(friction)) => Some(friction),
+        Some(Err(err)) => panic!("{}", err)
+    };
+
+    if mode == "travel" && rules.is_some() {
+        panic!("--rules can only be used with buffer mode");
+    } else if args.value_of("sensitivity").is_some() && (mode == "travel" || rules.is_some()) {
+        panic!("--sensitivity can only be used with buffer mode without --rules");
+    } else if args.is_present("wet-season") && (mode == "travel" || rules.is_some()) {
+        panic!("--wet-season can only be used with buffer mode without --rules");
+    } else if args.value_of("flood").is_some() && !args.is_present("wet-season") {
+        panic!("--flood requires --wet-season");
+    } else if args.value_of("proposed").is_some() && (mode == "travel" || rules.is_some()) {
+        panic!("--proposed can only be used with buffer mode without --rules");
+    } else if args.value_of("proposed").is_some() != args.value_of("proposed-output").is_some() {
+        panic!("--proposed & --proposed-output must be used together");
+    } else if mode != "travel" && friction.is_some() {
+        panic!("--friction can only be used with travel mode");
+    }
+
+    let urban_density = urban_density(args.value_of("urban-density"));
+
+    let cells: Option<Cells> = match args.value_of("grid") {
+        None => None,
+        Some(_) if args.value_of("bounds").is_some() => panic!("--grid cannot be used with --bounds"),
+        Some(grid) => match Cells::parse(grid) {
+            Ok(cells) => Some(cells),
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    if args.value_of("bounds-output").is_some() && args.value_of("bounds").is_none() && cells.is_none() {
+        panic!("--bounds-output requires --bounds or --grid");
+    }
+
+    let mut db = pool.get().unwrap();
+
+    let population = population(&mut db, args.value_of("population"));
+    println!("ok - using {} population", &population);
+
+    db.execute(format!("
+        CREATE SCHEMA IF NOT EXISTS country_{iso}
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+
+    let stages = Stages::new(&iso);
+    stages.create(&mut db);
+
+    if args.is_present("restart") {
+        stages.reset(&mut db, "");
+    }
+
+    let master = Network::new(format!("country_{}.master", &iso));
+    let country_boundary = args.value_of("country-boundary").map(|source| crate::pg::country::boundary(&iso, Some(source)));
+    let country = Country::new(format!("country_{}.country", &iso), country_boundary);
+
+    // Checksums of the input files, recorded with the run & used to fingerprint each stage
+    let network_checksum = file_fingerprint(&master_src);
+    let boundary_checksum = args.value_of("country-boundary").map(file_fingerprint);
+    let urban_checksum = args.value_of("urban").map(file_fingerprint);
+    let rules_checksum = args.value_of("rules").map(file_fingerprint);
+    let flood_checksum = args.value_of("flood").map(file_fingerprint);
+
+    let runs = Runs::new(&iso);
+    runs.create(&mut db);
+
+    let run = runs.start(&mut db, &serde_json::json!({
+        "network": runs::input(&master_src, &network_checksum),
+        "bounds": args.value_of("bounds").map(|bounds| {
+            bounds.split(',').map(|bounds| bounds.trim()).map(|bounds| {
+                runs::input(bounds, &file_fingerprint(bounds))
+            }).collect::<Vec<serde_json::Value>>()
+        }),
+        "urban": args.value_of("urban").map(|urban| runs::input(urban, urban_checksum.as_ref().unwrap())),
+        "country_boundary": args.value_of("country-boundary").map(|boundary| runs::input(boundary, boundary_checksum.as_ref().unwrap())),
+        "rules": args.value_of("rules").map(|rules| runs::input(rules, rules_checksum.as_ref().unwrap())),
+        "flood": args.value_of("flood").map(|flood| runs::input(flood, flood_checksum.as_ref().unwrap())),
+        "proposed": args.value_of("proposed").map(|proposed| runs::input(proposed, &file_fingerprint(proposed)))
+    }), &serde_json::json!({
+        "engine": "sql",
+        "population": &population,
+        "buffers": &buffers,
+        "mode": &mode,
+        "friction": &friction,
+        "urban_density": &urban_density,
+        "restart": args.is_present("restart"),
+        "wet_season": args.is_present("wet-season"),
+        "output": &output,
+        "grid": args.value_of("grid"),
+        "bounds_output": args.value_of("bounds-output"),
+        "sensitivity": args.value_of("sensitivity"),
+        "clusters": args.value_of("clusters"),
+        "proposed_output": args.value_of("proposed-output")
+    }));
+
+    // Fingerprints of the inputs of each stage, a stage is only rerun when its inputs change.
+    // The population dataset is identified by the checksum of its loaded files as well as its
+    // name, as a dataset can be reloaded with a different population
+    let population_checksum = Population::checksum(&mut db, &population).unwrap_or_default();
+    let raster_key = fingerprint(&[
+        &population,
+        &population_checksum,
+        &boundary_checksum.clone().unwrap_or_default(),
+        &urban_checksum.clone().unwrap_or_default(),
+        &urban_density.map(|density| density.to_string()).unwrap_or_default()
+    ]);
+    let country_key = boundary_checksum.clone().unwrap_or_default();
+    let network_key = network_checksum.clone();
+    let rules_key = rules_checksum.clone().unwrap_or_default();
+    let mut coverage_inputs: Vec<&str> = vec![
+        mode.as_str(),
+        friction.as_ref().map(|friction| friction.as_str()).unwrap_or(""),
+        rules_checksum.as_ref().map(|checksum| checksum.as_str()).unwrap_or("")
+    ];
+
+    // Only added when given, so that coverage calculated without a wet season remains complete
+    if args.is_present("wet-season") {
+        coverage_inputs.push(season::WET_SEASON);
+        coverage_inputs.push(flood_checksum.as_ref().map(|checksum| checksum.as_str()).unwrap_or(""));
+    }
+    let coverage_key = fingerprint(&coverage_inputs);
+
+    let subset = stages.status(&mut db, "raster", &raster_key) != Status::Complete;
+    let network = stages.status(&mut db, "network", &network_key) != Status::Complete;
+
+    // The country table is recreated whenever the boundary is given, removed or changed
+    let boundary = stages.status(&mut db, "country", &country_key) != Status::Complete;
+
+    // When only the network has changed, coverage is only recalculated for the pixels
+    // near roads that differ from those of the previously completed network
+    let incremental = network && !subset && stages.exists(&mut db, "network");
+    println!("ok - formatted database");
+
+    let poly = Polygon::new(format!("country_{}.bounds", &iso));
+    poly.create(&mut db);
+
+    let areas: Option<Areas> = match args.value_of("bounds") {
+        Some(bounds) => {
+            println!("ok - importing bounds files");
+
+            let areas = match Areas::load(bounds) {
+                Ok(areas) => areas,
+                Err(err) => panic!("{}", err)
+            };
+
+            import_areas(&mut db, &iso, &poly, &areas);
+
+            Some(areas)
+        },
+        None => None
+    };
+
+    let urban = Polygon::new(format!("country_{}.urban", &iso));
+    urban.create(&mut db);
+
+    match args.value_of("urban") {
+        Some(urban_src) if subset => {
+            println!("ok - importing urban mask");
+
+            urban.input(&mut db, PolyStream::new(
+                GeoStream::new(Some(urban_src.to_string())),
+                Some(String::from("/tmp/urban_error.log")))
+            );
+            urban.index(&mut db);
+
+            println!("ok - imported {} urban polygons", urban.count(&mut db));
+        },
+        _ => ()
+    };
+
+    let flood = Polygon::new(format!("country_{}.flood", &iso));
+
+    if args.is_present("wet-season") {
+        flood.create(&mut db);
+
+        if let Some(flood_src) = args.value_of("flood") {
+            println!("ok - importing flood polygons");
+
+            flood.input(&mut db, PolyStream::new(
+                GeoStream::new(Some(flood_src.to_string())),
+                Some(String::from("/tmp/flood_error.log")))
+            );
+        }
+        flood.index(&mut db);
+
+        println!("ok - imported {} flood polygons", flood.count(&mut db));
+    }
+
+    if incremental {
+        db.execute(format!("
+            DROP TABLE IF EXISTS country_{iso}.master_prev
+        ", iso = &iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            CREATE TABLE country_{iso}.master_prev AS
+                SELECT
+                    hash,
+                    buffer,
+                    geom
+                FROM
+                    country_{iso}.master
+        ", iso = &iso).as_str(), &[]).unwrap();
+    }
+
+    // The network stage is forgotten until the new network is imported, so that an
+    // interrupted import is not compared against on the next run
+    if network {
+        stages.reset(&mut db, "network");
+    }
+
+    let mut manager = Vec::with_capacity(2);
+    if network {
+        let mut db = pool.get().unwrap();
+        manager.push(thread::spawn(move || {
+            master.create(&mut db);
+            master.input(&mut db, NetStream::new(
+                    GeoStream::new(Some(master_src)),
+                    Some(String::from("/tmp/master_error.log")))
+                        );
+            master.index(&mut db);
+            println!("ok - imported {} master lines", master.count(&mut db));
+        }));
+    } else {
+        println!("ok - network is unchanged");
+    }
+
+    if subset && boundary {
+        stages.reset(&mut db, "country");
+    }
+
+    if subset {
+        let mut db = pool.get().unwrap();
+        manager.push(thread::spawn(move || {
+            if boundary || country.count(&mut db) == 0 {
+                country.create(&mut db);
+                country.index(&mut db);
+                println!("ok - imported {} country polygons", country.count(&mut db));
+            } else {
+                println!("ok - country table exists");
+            }
+        }));
+    }
+
+    for thread in manager {
+        thread.join().unwrap();
+    }
+
+    if subset {
+        stages.complete(&mut db, "country", &country_key);
+    }
+
+    // Grid cells are generated over the country table, so that a custom boundary is used
+    let areas: Option<Areas> = match cells {
+        None => areas,
+        Some(cells) => {
+            let areas = match cells.areas(&country_geom(&mut db, &iso)) {
+                Ok(areas) => areas,
+                Err(err) => panic!("{}", err)
+            };
+
+            import_areas(&mut db, &iso, &poly, &areas);
+
+            Some(areas)
+        }
+    };
+    timings.lap("import");
+
+    if subset {
+        // Pixel ids are reassigned, so all coverage must be recalculated
+        stages.reset(&mut db, "coverage");
+
+        let has_urban = urban.count(&mut db) > 0;
+        raster(&mut db, &iso, &population, has_urban, urban_density);
+        stages.complete(&mut db, "raster", &raster_key);
+    } else {
+        println!("ok - raster subset is unchanged");
+    }
+    timings.lap("raster");
+
+    if network {
+        db.execute(format!("
+            ALTER TABLE country_{iso}.master
+                ADD COLUMN geom_buff GEOMETRY(MultiPolygon, 4326)
+        ", iso = &iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            ALTER TABLE country_{iso}.master
+                ADD COLUMN class TEXT,
+                ADD COLUMN buffer BIGINT,
+                ADD COLUMN weight FLOAT8,
+                ADD COLUMN hash TEXT
+        ", iso = &iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            UPDATE country_{iso}.master
+                SET hash = md5(props::TEXT || ST_AsEWKT(geom))
+        ", iso = &iso).as_str(), &[]).unwrap();
+
+        weigh(&mut db, &iso, &rules);
+
+        db.execute(format!("
+            CREATE INDEX master_buff_idx
+                ON country_{iso}.master USING GIST (geom_buff)
+        ", iso = &iso).as_str(), &[]).unwrap();
+
+        if incremental {
+            touched(&mut db, &iso, *buffers.iter().max().unwrap());
+            stages.stale(&mut db, "coverage");
+        } else {
+            stages.reset(&mut db, "coverage");
+        }
+
+        stages.complete(&mut db, "network", &network_key);
+    } else if stages.status(&mut db, "rules", &rules_key) != Status::Complete {
+        // Rules are not part of the network key, so are reapplied to an unchanged
+        // network whenever they differ from the rules last applied to it
+        println!("ok - applying changed rules");
+        weigh(&mut db, &iso, &rules);
+    }
+    stages.complete(&mut db, "rules", &rules_key);
+    timings.lap("network");
+
+    let statuses: Vec<Status> = buffers.iter().map(|buffer| {
+        stages.status(&mut db, &format!("coverage:{}", buffer), &coverage_key)
+    }).collect();
+
+    // Travel cost is calculated across the whole grid, so is always recalculated in full
+    if mode == "travel" && statuses.iter().any(|status| *status != Status::Complete) {
+        println!("ok - calculating travel cost");
+        travel::travel(&mut db, &iso, friction.as_ref().map(|friction| friction.as_str()));
+    }
+
+    // Distances are processed in reverse so that the coverage geometry left in {iso}_geom
+    // for the viz module is the one belonging to the first distance given
+    for (buffer, status) in buffers.iter().zip(statuses.iter()).rev() {
+        let stage = format!("coverage:{}", buffer);
+
+        if *status == Status::Complete {
+            println!("ok - {}m coverage is complete", buffer);
+            continue;
+        }
+
+        if mode == "travel" {
+            travel_coverage(&mut db, &iso, *buffer);
+        } else {
+            coverage(&pool, &iso, *buffer, rules.is_some(), *status == Status::Stale);
+        }
+
+        if args.is_present("wet-season") {
+            season::coverage(&pool, &iso, *buffer);
+        }
+
+        stages.complete(&mut db, &stage, &coverage_key);
+    }
+    timings.lap("coverage");
+
+    let mut wtr = csv::Writer::from_path(output).unwrap();
+
+    wtr.write_record(&HEADER).unwrap();
+
+    let mut bounds_output: Option<Output> = match args.value_of("bounds-output") {
+        None => None,
+        Some(path) => match Output::new(path) {
+            Ok(output) => Some(output),
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    if let Some(ref areas) = areas {
+        leaf_fractions(&mut db, &iso, &areas.leaves());
+    }
+
+    // Only rural population contributes to the covered & uncovered figures, as per
+    // the World Bank definition of the RAI. Without an urban mask all population is rural
+    //
+    // When a rule set is given, the standalone coverage of each road class is output
+    // in addition to the coverage of the network as a whole (class: all)
+    let mut national: Vec<serde_json::Value> = Vec::with_capacity(buffers.len());
+    let mut results: Vec<(i64, Totals)> = Vec::new();
+    for buffer in buffers.iter() {
+        match db.query(format!("
+            SELECT
+                'country',
+                COALESCE(cov.class, 'all'),
+                {totals}
+            FROM
+                country_{iso}.{iso}_geom AS px
+                    INNER JOIN country_{iso}.{iso}_coverage AS cov
+                        ON px.id = cov.id
+            WHERE
+                cov.buffer = $1
+            GROUP BY
+                cov.class
+            ORDER BY
+                cov.class NULLS FIRST
+        ", iso = &iso, totals = TOTALS).as_str(), &[buffer]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => {
+                for totals in write_rows(&mut wtr, &population, *buffer, &res) {
+                    if totals.class == "all" {
+                        national.push(runs::result(*buffer, &totals));
+                    }
+
+                    results.push((*buffer, totals));
+                }
+            }
+        };
+
+        if let Some(ref areas) = areas {
+            // Pixels on the boundary of leaf areas are split between them by area,
+            // with all other areas being the sum of the leaf areas within them
+            let leaves: Vec<(usize, Totals)> = match db.query(format!("
+                SELECT
+                    '',
+                    COALESCE(cov.class, 'all'),
+                    {totals},
+                    px.bounds
+                FROM
+                    (
+                        SELECT
+                            frac.bounds,
+                            px.id,
+                            px.urban,
+                            px.pop * frac.frac AS pop
+                        FROM
+                            country_{iso}.{iso}_geom AS px
+                                INNER JOIN country_{iso}.{iso}_bounds_px AS frac
+                                    ON px.id = frac.id
+                    ) AS px
+                        INNER JOIN country_{iso}.{iso}_coverage AS cov
+                            ON px.id = cov.id
+                WHERE
+                    cov.buffer = $1
+                GROUP BY
+                    px.bounds,
+                    cov.class
+            ", iso = &iso, totals = TOTALS).as_str(), &[buffer]) {
+                Err(err) => panic!("{}", err),
+                Ok(res) => res.iter().map(|row| {
+                    let bounds: i64 = row.get(7);
+                    (bounds as usize, Totals::from_row(row))
+                }).collect()
+            };
+
+            for (i, totals) in areas.rollup(leaves) {
+                wtr.write_record(&totals.as_record(&population, *buffer)).unwrap();
+
+                if let Some(ref mut output) = bounds_output {
+                    output.write(&totals, &population, *buffer, &areas.areas[i].geom);
+                }
+
+                results.push((*buffer, totals));
+            }
+        }
+    }
+
+    if let Some(output) = bounds_output {
+        output.finish();
+    }
+    timings.lap("results");
+
+    if let Some(path) = args.value_of("sensitivity") {
+        sensitivity::report(&pool, &iso, buffers[0], &population, areas.as_ref(), path);
+        timings.lap("sensitivity");
+    }
+
+    if let Some(path) = args.value_of("clusters") {
+        clusters::report(&mut db, &iso, buffers[0], path);
+        timings.lap("clusters");
+    }
+
+    if let (Some(source), Some(path)) = (args.value_of("proposed"), args.value_of("proposed-output")) {
+        let country = match results.iter().find(|(buffer, totals)| *buffer == buffers[0] && totals.id == "country" && totals.class == "all") {
+            Some((_, country)) => country,
+            None => panic!("No national result of the {}m access distance", buffers[0])
+        };
+
+        proposals::report(&pool, &iso, buffers[0], source, country, path);
+        timings.lap("proposed");
+    }
+
+    // Pixel ids are only stable until the raster subset is next rebuilt, even with the same inputs,
+    // the raster key including the checksum of the population dataset
+    let grid = fingerprint(&[
+        &raster_key,
+        &stages.completed(&mut db, "raster").unwrap_or_default()
+    ]);
+    runs.snapshot(&mut db, run, &grid, &results);
+    runs.finish(&mut db, run, &timings.to_json(), &serde_json::Value::from(national));
+    println!("ok - recorded run {}", run);
+}
+
+///
+/// Insert bounds areas into the bounds table, identified by their index
+///
+fn import_areas(db: &mut postgres::Client, iso: &str, poly: &Polygon, areas: &Areas) {
+    for (i, area) in areas.areas.iter().enumerate() {
+        db.execute(format!("
+            INSERT INTO country_{iso}.bounds (
+                id,
+                name,
+                props,
+                geom
+            ) VALUES (
+                $1,
+                $2,
+                $3,
+                ST_Multi(ST_CollectionExtract(ST_MakeValid(ST_SetSRID(ST_GeomFromGeoJSON($4), 4326)), 3))
+            )
+        ", iso = &iso).as_str(), &[
+            &(i as i64),
+            &area.name,
+            &serde_json::json!({
+                "id": area.id,
+                "parent": area.parent.map(|parent| areas.areas[parent].id.clone()),
+                "level": area.level
+            }),
+            &geojson::Geometry::new(geojson::Value::from(&area.geom)).to_string()
+        ]).unwrap();
+    }
+
+    // Residual areas are the remainder of their parent outside of all of its other children
+    for (i, area) in areas.areas.iter().enumerate().filter(|(_, area)| area.residual) {
+        let parent = area.parent.unwrap();
+        let children: Vec<i64> = areas.children(parent).into_iter().map(|child| child as i64).collect();
+
+        db.execute(format!("
+            UPDATE country_{iso}.bounds
+                SET geom = ST_Multi(ST_CollectionExtract(ST_Difference(parent.geom, children.geom), 3))
+                FROM
+                    country_{iso}.bounds AS parent,
+                    (
+                        SELECT
+                            ST_Union(geom) AS geom
+                        FROM
+                            country_{iso}.bounds
+                        WHERE
+                            id = ANY($3)
+                    ) AS children
+                WHERE
+                    bounds.id = $1
+                    AND parent.id = $2
+        ", iso = &iso).as_str(), &[&(i as i64), &(parent as i64), &children]).unwrap();
+    }
+    poly.index(db);
+
+    println!("ok - imported {} bounds areas", areas.areas.len());
+}
+
+///
+/// Return the geometry of the country table
+///
+fn country_geom(db: &mut postgres::Client, iso: &str) -> geo::MultiPolygon<f64> {
+    let geom: String = match db.query(format!("
+        SELECT
+            ST_AsGeoJSON(ST_Multi(ST_CollectionExtract(ST_Collect(geom), 3)))
+        FROM
+            country_{iso}.country
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.get(0).unwrap().get(0)
+    };
+
+    let geom: geojson::Geometry = match geom.parse() {
+        Ok(geojson::GeoJson::Geometry(geom)) => geom,
+        _ => panic!("Invalid country geometry")
+    };
+
+    match output::multipolygon(geom.value) {
+        Ok(geom) => geom,
+        Err(err) => panic!("Invalid country geometry: {}", err)
+    }
+}
+
+///
+/// Record the fraction of the population of each pixel apportioned to each leaf bounds area,
+/// so that pixels crossing a boundary are split between areas rather than counted twice
+///
+/// Fractions are relative to the area of the pixel within any leaf area, so that the portion
+/// of a pixel outside of all areas (ie beyond the coastline) is not lost from the results
+///
+fn leaf_fractions(db: &mut postgres::Client, iso: &str, leaves: &Vec<usize>) {
+    let leaves: Vec<i64> = leaves.iter().map(|leaf| *leaf as i64).collect();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_bounds_px
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_bounds_px AS
+            SELECT
+                parts.bounds,
+                parts.id,
+                parts.area / SUM(parts.area) OVER (PARTITION BY parts.id) AS frac
+            FROM (
+                SELECT
+                    bounds.id AS bounds,
+                    px.id,
+                    CASE
+                        WHEN ST_Within(px.geom, bounds.geom) THEN ST_Area(px.geom)
+                        ELSE ST_Area(ST_Intersection(bounds.geom, px.geom))
+                    END AS area
+                FROM
+                    country_{iso}.bounds AS bounds
+                        INNER JOIN country_{iso}.{iso}_geom AS px
+                            ON ST_Intersects(bounds.geom, px.geom)
+                WHERE
+                    bounds.id = ANY($1)
+            ) AS parts
+            WHERE
+                parts.area > 0
+    ", iso = &iso).as_str(), &[&leaves]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_bounds_px_idx ON country_{iso}.{iso}_bounds_px (id)
+    ", iso = &iso).as_str(), &[]).unwrap();
+}
+
+///
+/// Columns of the CSV output
+///
+const HEADER: [&str; 12] = [
+    "id",
+    "parent",
+    "level",
+    "name",
+    "population",
+    "buffer",
+    "class",
+    "covered population",
+    "uncovered population",
+    "rural population",
+    "urban population",
+    "total population"
+];
+
+///
+/// Aggregate columns shared by all result queries, in the order of the CSV output
+///
+const TOTALS: &str = "
+    COALESCE(SUM(px.pop * cov.coverage * 0.01) FILTER (WHERE NOT px.urban), 0.0),
+    COALESCE(SUM(px.pop * (100 - cov.coverage) * 0.01) FILTER (WHERE NOT px.urban), 0.0),
+    COALESCE(SUM(px.pop) FILTER (WHERE NOT px.urban), 0.0),
+    COALESCE(SUM(px.pop) FILTER (WHERE px.urban), 0.0),
+    COALESCE(SUM(px.pop), 0.0)
+";
+
+///
+/// Covered & uncovered population for a single named area
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Totals {
+    pub id: String,
+    pub parent: String,
+    pub level: i64,
+    pub name: String,
+    pub class: String,
+    pub covered: f64,
+    pub uncovered: f64,
+    pub rural: f64,
+    pub urban: f64,
+    pub total: f64
+}
+
+impl Totals {
+    pub fn new(id: &str, parent: &str, level: i64, name: &str, class: &str) -> Self {
+        Totals {
+            id: id.to_string(),
+            parent: parent.to_string(),
+            level: level,
+            name: name.to_string(),
+            class: class.to_string(),
+            covered: 0.0,
+            uncovered: 0.0,
+            rural: 0.0,
+            urban: 0.0,
+            total: 0.0
+        }
+    }
+
+    fn from_row(row: &postgres::Row) -> Self {
+        let name: String = row.get(0);
+
+        Totals {
+            id: name.clone(),
+            parent: String::new(),
+            level: 0,
+            name: name,
+            class: row.get(1),
+            covered: row.get(2),
+            uncovered: row.get(3),
+            rural: row.get(4),
+            urban: row.get(5),
+            total: row.get(6)
+        }
+    }
+
+    ///
+    /// Class coverage is only recorded for pixels a class reaches, so the population
+    /// figures of a class are taken from the network (all) totals of the same area
+    ///
+    fn with_network(mut self, network: &Totals) -> Self {
+        self.uncovered = network.rural - self.covered;
+        self.rural = network.rural;
+        self.urban = network.urban;
+        self.total = network.total;
+
+        self
+    }
+
+    ///
+    /// Percentage of the rural population that is covered, if there is any
+    ///
+    pub fn rai(&self) -> Option<f64> {
+        if self.rural > 0.0 {
+            Some(self.covered / self.rural * 100.0)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Add the population figures of another set of totals
+    ///
+    fn sum(&mut self, other: &Totals) {
+        self.covered += other.covered;
+        self.uncovered += other.uncovered;
+        self.rural += other.rural;
+        self.urban += other.urban;
+        self.total += other.total;
+    }
+
+    fn as_record(&self, population: &str, buffer: i64) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.parent.clone(),
+            self.level.to_string(),
+            self.name.clone(),
+            population.to_string(),
+            buffer.to_string(),
+            self.class.clone(),
+            self.covered.to_string(),
+            self.uncovered.to_string(),
+            self.rural.to_string(),
+            self.urban.to_string(),
+            self.total.to_string()
+        ]
+    }
+}
+
+///
+/// Write result rows ordered by name with the network (all) row for each name first,
+/// returning the totals written
+///
+fn write_rows(wtr: &mut csv::Writer<std::fs::File>, population: &str, buffer: i64, rows: &Vec<postgres::Row>) -> Vec<Totals> {
+    let mut network: Option<Totals> = None;
+    let mut written = Vec::with_capacity(rows.len());
+
+    for row in rows.iter() {
+        let mut totals = Totals::from_row(row);
+
+        if totals.class == "all" {
+            network = Some(totals.clone());
+        } else {
+            match network {
+                Some(ref network) if network.name == totals.name => {
+                    totals = totals.with_network(network);
+                },
+                _ => ()
+            };
+        }
+
+        wtr.write_record(&totals.as_record(population, buffer)).unwrap();
+        written.push(totals);
+    }
+
+    written
+}
+
+///
+/// Return the name of the population dataset to calculate RAI with, defaulting to
+/// the only loaded dataset, or the unnamed pop.population table of earlier imports
+///
+fn population(db: &mut postgres::Client, name: Option<&str>) -> String {
+    let datasets: Vec<String> = Population::list(db).into_iter().map(|dataset| dataset.0).collect();
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => match datasets.len() {
+            0 => String::from("population"),
+            1 => datasets[0].clone(),
+            _ => panic!("--population must be one of the loaded datasets: {}", datasets.join(", "))
+        }
+    };
+
+    match db.query("
+        SELECT to_regclass('pop.' || $1) IS NOT NULL
+    ", &[&name]) {
+        Ok(ref res) if res.get(0).unwrap().get(0) => name,
+        _ => panic!("{} population dataset is not loaded", &name)
+    }
+}
+
+///
+/// Parse the population density (people/km2) at or above which population is urban
+///
+fn urban_density(input: Option<&str>) -> Option<f64> {
+    match input {
+        None => None,
+        Some(density) => match density.parse::<f64>() {
+            Ok(density) if density > 0.0 => Some(density),
+            _ => panic!("--urban-density value must be a positive number")
+        }
+    }
+}
+
+///
+/// Parse the comma separated list of access distances (metres) given to calc,
+/// defaulting to the 2km distance defined by the RAI
+///
+pub fn buffers(input: Option<&str>) -> Vec<i64> {
+    let input = match input {
+        None => { return vec![2000]; },
+        Some(input) => input
+    };
+
+    let mut buffers: Vec<i64> = Vec::new();
+    for buffer in input.split(',') {
+        let buffer = match buffer.trim().parse::<i64>() {
+            Ok(buffer) if buffer > 0 => buffer,
+            _ => panic!("--buffer values must be positive integers")
+        };
+
+        if !buffers.contains(&buffer) {
+            buffers.push(buffer);
+        }
+    }
+
+    if buffers.is_empty() {
+        panic!("--buffer must contain at least one distance");
+    }
+
+    buffers
+}
+
+///
+/// Create the raster subset of the country & the population pixels within it, replacing
+/// any existing subset along with the coverage calculated for it
+///
+fn raster(db: &mut postgres::Client, iso: &String, population: &String, urban: bool, urban_density: Option<f64>) {
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_raster
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_buffer
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_geom
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_coverage
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_raster AS
+            SELECT
+                rid,
+                ST_Clip(rast, geom, true) AS rast
+            FROM
+                {population},
+                country_{iso}.country
+            WHERE
+                LOWER(country.iso) = LOWER($1)
+                AND ST_Intersects(rast, geom)
+    ", iso = &iso, population = Population::new(population).name()).as_str(), &[iso]).unwrap();
+    println!("ok - created raster subset");
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_raster
+            ADD PRIMARY KEY (rid)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_geom AS
+            SELECT
+                gv.rid AS rid,
+                (gv.geom).val AS pop,
+                (gv.geom).geom AS geom
+            FROM (
+                SELECT
+                    rid,
+                    ST_PixelAsPolygons(rast) AS geom
+                FROM
+                    country_{iso}.{iso}_raster
+                ) gv
+            WHERE
+                (gv.geom).val > 0
+    ", iso = &iso).as_str(), &[]).unwrap();
+    println!("ok - created population areas");
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_geom
+            ADD COLUMN coverage INT
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_geom
+            ADD COLUMN coverage_geom GEOMETRY(MULTIPOLYGON, 4326)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_geom_gidx
+            ON country_{iso}.{iso}_geom USING GIST (geom)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_geom
+            ADD COLUMN id BIGSERIAL
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_geom_idx
+            ON country_{iso}.{iso}_geom(id)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_geom_rid_idx
+            ON country_{iso}.{iso}_geom(rid)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_geom
+            ADD COLUMN urban BOOLEAN NOT NULL DEFAULT FALSE
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    if urban {
+        db.execute(format!("
+            UPDATE country_{iso}.{iso}_geom AS px
+                SET urban = TRUE
+                FROM country_{iso}.urban AS urban
+                WHERE
+                    ST_Intersects(urban.geom, ST_Centroid(px.geom))
+        ", iso = &iso).as_str(), &[]).unwrap();
+    }
+
+    match urban_density {
+        Some(density) => {
+            db.execute(format!("
+                UPDATE country_{iso}.{iso}_geom
+                    SET urban = TRUE
+                    WHERE
+                        pop / (ST_Area(geom::GEOGRAPHY) / 1000000) >= $1
+            ", iso = &iso).as_str(), &[&density]).unwrap();
+        },
+        None => ()
+    };
+
+    match db.query(format!("
+        SELECT
+            count(*)
+        FROM
+            country_{iso}.{iso}_geom
+        WHERE
+            urban
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => {
+            let urban_px: i64 = res.get(0).unwrap().get(0);
+            println!("ok - masked {} urban population areas", urban_px);
+        }
+    };
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_coverage (
+            id BIGINT,
+            buffer BIGINT,
+            class TEXT,
+            coverage INT
+        )
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.{iso}_geom
+            ADD COLUMN travel FLOAT8
+    ", iso = &iso).as_str(), &[]).unwrap();
+}
+
+///
+/// Populate the class, buffer & weight of each road of the master network from a rule set,
+/// or the default rules without one
+///
+fn weigh(db: &mut postgres::Client, iso: &String, rules: &Option<Rules>) {
+    match rules {
+        Some(ref rules) => rules.apply(db, &format!("country_{}.master", &iso)),
+        None => Rules::default().apply(db, &format!("country_{}.master", &iso))
+    };
+}
+
+///
+/// Record the pixels within reach of roads that were added or removed since the previously
+/// completed network in {iso}_touched, so that only their coverage is recalculated
+///
+fn touched(db: &mut postgres::Client, iso: &String, buffer: i64) {
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_touched
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_touched AS
+            SELECT DISTINCT
+                px.id
+            FROM
+                country_{iso}.{iso}_geom AS px,
+                (
+                    SELECT
+                        prev.buffer,
+                        prev.geom
+                    FROM
+                        country_{iso}.master_prev AS prev
+                    WHERE
+                        prev.hash NOT IN (SELECT hash FROM country_{iso}.master)
+                    UNION ALL
+                    SELECT
+                        master.buffer,
+                        master.geom
+                    FROM
+                        country_{iso}.master AS master
+                    WHERE
+                        master.hash NOT IN (SELECT hash FROM country_{iso}.master_prev)
+                ) AS changed
+            WHERE
+                ST_DWithin(changed.geom::GEOGRAPHY, px.geom::GEOGRAPHY, COALESCE(changed.buffer, $1))
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_touched_idx
+            ON country_{iso}.{iso}_touched(id)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    match db.query(format!("
+        SELECT count(*) FROM country_{iso}.{iso}_touched
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => {
+            let touched: i64 = res.get(0).unwrap().get(0);
+            println!("ok - network changes affect {} population areas", touched);
+        }
+    };
+}
+
+///
+/// Buffer the master network by the given distance and calculate the percentage of each
+/// population pixel that falls within it, recording the result in {iso}_coverage
+///
+/// Roads with a class specific buffer are buffered by that distance instead. If the
+/// network is weighted, pixel coverage is weighted by road class & the standalone
+/// coverage of each class is also recorded
+///
+/// If incremental, only the coverage of the pixels in {iso}_touched is recalculated
+///
+fn coverage(pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, iso: &String, buffer: i64, weighted: bool, incremental: bool) {
+    let mut db = pool.get().unwrap();
+
+    let touched = if incremental {
+        format!("AND px.id IN (SELECT id FROM country_{iso}.{iso}_touched)", iso = &iso)
+    } else {
+        String::from("")
+    };
+
+    db.execute(format!("
+        DELETE
+            FROM
+                country_{iso}.{iso}_coverage AS px
+            WHERE
+                buffer = $1
+                {touched}
+    ", iso = &iso, touched = &touched).as_str(), &[&buffer]).unwrap();
+
+    // Invalid buffers are repaired rather than their roads removed, as the master network
+    // is reused by every other access distance
+    db.execute(format!("
+        UPDATE country_{iso}.master
+            SET geom_buff = ST_Multi(ST_Buffer(geom::GEOGRAPHY, COALESCE(buffer, {buffer}))::GEOMETRY)
+    ", iso = &iso, buffer = buffer).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        UPDATE country_{iso}.master
+            SET geom_buff = ST_Multi(ST_CollectionExtract(ST_MakeValid(geom_buff), 3))
+            WHERE
+                NOT ST_IsValid(geom_buff)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        ANALYZE country_{iso}.master
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    println!("ok - buffered network by {}m", buffer);
+
+    let rids: Vec<i32> = match db.query(format!("
+        SELECT DISTINCT
+            px.rid
+        FROM
+            country_{iso}.{iso}_geom AS px
+        WHERE
+            TRUE
+            {touched}
+    ", iso = &iso, touched = &touched).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| row.get(0)).collect()
+    };
+
+    // Coverage geometry is left over from the previous distance when calculating multiple distances
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom AS px
+            SET coverage_geom = NULL
+            WHERE
+                TRUE
+                {touched}
+    ", iso = &iso, touched = &touched).as_str(), &[]).unwrap();
+
+    println!("ok - calculating coverage geometry\n");
+
+    overlay(pool, iso, &format!("country_{}.master", &iso), "geom_buff", &rids, &touched);
+
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom AS px
+            SET coverage_geom = overlay.geom
+            FROM country_{iso}.{iso}_overlay AS overlay
+            WHERE
+                px.id = overlay.id
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom AS px
+            SET coverage = ROUND(LEAST(COALESCE(ST_Area(coverage_geom), 0.0) / ST_Area(geom), 1) * 100)
+            WHERE
+                TRUE
+                {touched}
+    ", iso = &iso, touched = &touched).as_str(), &[]).unwrap();
+
+    if weighted {
+        println!("ok - calculating weighted coverage\n");
+
+        let ids: Vec<i64> = match db.query(format!("
+            SELECT
+                px.id
+            FROM
+                country_{iso}.{iso}_geom AS px
+            WHERE
+                TRUE
+                {touched}
+        ", iso = &iso, touched = &touched).as_str(), &[]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.iter().map(|row| row.get(0)).collect()
+        };
+
+        let pb = ProgressBar::new(ids.len() as u64);
+
+        ids.par_iter().for_each(|id| {
+            weighted_coverage(&mut pool.get().unwrap(), iso, buffer, *id);
+            pb.inc(1);
+        });
+        pb.finish();
+    }
+
+    db.execute(format!("
+        INSERT INTO country_{iso}.{iso}_coverage (
+            id,
+            buffer,
+            coverage
+        ) SELECT
+            px.id,
+            $1,
+            px.coverage
+        FROM
+            country_{iso}.{iso}_geom AS px
+        WHERE
+            TRUE
+            {touched}
+    ", iso = &iso, touched = &touched).as_str(), &[&buffer]).unwrap();
+
+    println!("\nok - done calculating {}m coverage geometry", buffer);
+}
+
+///
+/// Mark population pixels within the given travel cost of a road as covered,
+/// recording the result in {iso}_coverage
+///
+fn travel_coverage(db: &mut postgres::Client, iso: &String, buffer: i64) {
+    db.execute(format!("
+        DELETE
+            FROM
+                country_{iso}.{iso}_coverage
+            WHERE
+                buffer = $1
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom
+            SET
+                coverage = CASE WHEN travel <= $1 THEN 100 ELSE 0 END,
+                coverage_geom = CASE WHEN travel <= $1 THEN ST_Multi(geom) ELSE NULL END
+    ", iso = &iso).as_str(), &[&(buffer as f64)]).unwrap();
+
+    db.execute(format!("
+        INSERT INTO country_{iso}.{iso}_coverage (
+            id,
+            buffer,
+            coverage
+        ) SELECT
+            id,
+            $1,
+            coverage
+        FROM
+            country_{iso}.{iso}_geom
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    println!("ok - done calculating {}m travel coverage", buffer);
+}
+
+///
+/// Intersect the pixels of the given raster tiles with a buffered network, recording the
+/// covered geometry & coverage of each pixel the network reaches in {iso}_overlay
+///
+/// Pixels are processed a raster tile at a time, intersecting each pixel with the union of the
+/// buffered network around the tile. As the intersection of a pixel with the union is the union
+/// of its intersections with each buffer, the resulting coverage is identical to doing so per pixel
+///
+fn overlay(
+    pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>,
+    iso: &String,
+    source: &str,
+    column: &str,
+    rids: &Vec<i32>,
+    touched: &str
+) {
+    let mut db = pool.get().unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_overlay
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_overlay (
+            id BIGINT,
+            coverage INT,
+            geom GEOMETRY(MULTIPOLYGON, 4326)
+        )
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    let pb = ProgressBar::new(rids.len() as u64);
+
+    rids.par_iter().for_each(|rid| {
+        pool.get().unwrap().execute(format!("
+            WITH network AS (
+                SELECT
+                    ST_Union(source.{column}) AS geom
+                FROM
+                    {source} AS source,
+                    country_{iso}.{iso}_raster AS raster
+                WHERE
+                    raster.rid = $1
+                    AND ST_Intersects(source.{column}, ST_Envelope(raster.rast))
+            )
+            INSERT INTO country_{iso}.{iso}_overlay (
+                id,
+                coverage,
+                geom
+            ) SELECT
+                px.id,
+                ROUND(LEAST(COALESCE(ST_Area(px.geom_cov), 0.0) / ST_Area(px.geom), 1) * 100),
+                px.geom_cov
+            FROM (
+                SELECT
+                    px.id,
+                    px.geom,
+                    ST_Multi(ST_CollectionExtract(ST_Intersection(network.geom, px.geom), 3)) AS geom_cov
+                FROM
+                    country_{iso}.{iso}_geom AS px,
+                    network
+                WHERE
+                    px.rid = $1
+                    AND network.geom IS NOT NULL
+                    AND ST_Intersects(network.geom, px.geom)
+                    {touched}
+            ) AS px
+        ", iso = &iso, source = source, column = column, touched = touched).as_str(), &[rid]).unwrap();
+        pb.inc(1);
+    });
+    pb.finish();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_overlay_idx
+            ON country_{iso}.{iso}_overlay (id)
+    ", iso = &iso).as_str(), &[]).unwrap();
+}
+
+///
+/// Calculate the weighted coverage of a single pixel, as well as the weighted
+/// standalone coverage of each road class that reaches it
+///
+fn weighted_coverage(db: &mut postgres::Client, iso: &String, buffer: i64, id: i64) {
+    let area: f64 = match db.query(format!("
+        SELECT
+            ST_Area(geom)
+        FROM
+            country_{iso}.{iso}_geom
+        WHERE
+            id = $1
+    ", iso = &iso).as_str(), &[&id]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.get(0).unwrap().get(0)
+    };
+
+    // Area covered by roads at or above each weight, both for the network
+    // as a whole (NULL class) and for each class on its own
+    let rows = match db.query(format!("
+        WITH lines AS (
+            SELECT
+                master.class,
+                master.weight,
+                ST_Intersection(master.geom_buff, px.geom) AS geom
+            FROM
+                country_{iso}.master,
+                country_{iso}.{iso}_geom px
+            WHERE
+                ST_Intersects(master.geom_buff, px.geom)
+                AND px.id = $1
+        )
+        SELECT
+            NULL::TEXT AS class,
+            tiers.weight,
+            ST_Area(ST_Union(lines.geom))
+        FROM
+            (SELECT DISTINCT weight FROM lines) AS tiers
+                INNER JOIN lines
+                    ON lines.weight >= tiers.weight
+        GROUP BY
+            tiers.weight
+        UNION ALL
+        SELECT
+            tiers.class,
+            tiers.weight,
+            ST_Area(ST_Union(lines.geom))
+        FROM
+            (SELECT DISTINCT class, weight FROM lines) AS tiers
+                INNER JOIN lines
+                    ON lines.class = tiers.class
+                    AND lines.weight >= tiers.weight
+        GROUP BY
+            tiers.class,
+            tiers.weight
+    ", iso = &iso).as_str(), &[&id]) {
+        Err(err) => panic!("{}", err),
+        Ok(rows) => rows
+    };
+
+    let mut tiers: HashMap<Option<String>, Vec<(f64, f64)>> = HashMap::new();
+    for row in rows.iter() {
+        let class: Option<String> = row.get(0);
+        let weight: f64 = row.get(1);
+        let tier_area: f64 = row.get(2);
+
+        tiers.entry(class).or_insert_with(Vec::new).push((weight, tier_area));
+    }
+
+    let network = match tiers.remove(&None) {
+        None => 0,
+        Some(network) => (rules::weighted(network, area) * 100.0).round() as i32
+    };
+
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom
+            SET coverage = $2
+            WHERE
+                id = $1
+    ", iso = &iso).as_str(), &[&id, &network]).unwrap();
+
+    for (class, class_tiers) in tiers {
+        let class_coverage = (rules::weighted(class_tiers, area) * 100.0).round() as i32;
+
+        db.execute(format!("
+            INSERT INTO country_{iso}.{iso}_coverage (
+                id,
+                buffer,
+                class,
+                coverage
+            ) VALUES (
+                $1,
+                $2,
+                $3,
+                $4
+            )
+        ", iso = &iso).as_str(), &[&id, &buffer, &class, &class_coverage]).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffers() {
+        assert_eq!(buffers(None), vec![2000]);
+        assert_eq!(buffers(Some("5000")), vec![5000]);
+        assert_eq!(buffers(Some("2000, 5000,2000")), vec![2000, 5000]);
+    }
+
+    #[test]
+    fn test_totals_with_network() {
+        let network = Totals {
+            id: String::from("country"),
+            parent: String::new(),
+            level: 0,
+            name: String::from("country"),
+            class: String::from("all"),
+            covered: 75.0,
+            uncovered: 25.0,
+            rural: 100.0,
+            urban: 50.0,
+            total: 150.0
+        };
+
+        let class = Totals {
+            id: String::from("country"),
+            parent: String::new(),
+            level: 0,
+            name: String::from("country"),
+            class: String::from("residential"),
+            covered: 10.0,
+            uncovered: 0.0,
+            rural: 20.0,
+            urban: 0.0,
+            total: 20.0
+        };
+
+        assert_eq!(class.with_network(&network), Totals {
+            id: String::from("country"),
+            parent: String::new(),
+            level: 0,
+            name: String::from("country"),
+            class: String::from("residential"),
+            covered: 10.0,
+            uncovered: 90.0,
+            rural: 100.0,
+            urban: 50.0,
+            total: 150.0
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "--buffer values must be positive integers")]
+    fn test_buffers_invalid() {
+        buffers(Some("2000,-1"));
+    }
+}
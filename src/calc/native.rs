@@ -0,0 +1,621 @@
+use std::collections::BTreeMap;
+use geo::algorithm::bounding_rect::BoundingRect;
+use geo::algorithm::euclidean_distance::EuclideanDistance;
+use geo::algorithm::intersects::Intersects;
+use geo::algorithm::contains::Contains;
+use geo::algorithm::centroid::Centroid;
+use rayon::prelude::*;
+use crate::grid::Extent;
+use crate::raster::Raster;
+use crate::stream::{GeoStream, NetStream, PolyStream};
+use crate::grid::{LAT_METRES, LNG_METRES};
+use super::{Totals, HEADER};
+use super::admin::Areas;
+use super::cells::Cells;
+use super::output::{Output, multipolygon};
+use super::runs::{self, Runs, Timings};
+use super::stages::file_fingerprint;
+
+/// Number of samples along each axis of a cell used to measure partial coverage
+const SAMPLES: usize = 10;
+
+///
+/// A single populated cell of the population raster
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pixel {
+    pub col: usize,
+    pub row: usize,
+    pub pop: f64,
+    pub urban: bool
+}
+
+///
+/// Calculate the RAI in process from population grid files, without PostgreSQL
+///
+/// The results are written in the same format as the SQL engine, which remains
+/// as a reference implementation to cross-check results against
+///
+/// Given a database, the run & the results of each area are recorded along with
+/// those of the SQL engine. As there is no pixel table, the coverage of each pixel
+/// is not kept, so native runs cannot be compared by the diff module with --pixels
+///
+pub fn main(pool: Option<r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>>, args: &clap_v3::ArgMatches) {
+    let mut timings = Timings::new();
+
+    let iso = args.value_of("iso").unwrap().to_string().to_lowercase();
+    println!("ok - processing {} (native)", &iso);
+
+    let master_src = args.value_of("NETWORK").unwrap().to_string();
+    let output = args.value_of("output").unwrap().to_string();
+
+    let buffers = super::buffers(args.value_of("buffer"));
+    let urban_density = super::urban_density(args.value_of("urban-density"));
+
+    if args.value_of("rules").is_some() {
+        panic!("--rules is not supported by the native engine");
+    } else if args.value_of("sensitivity").is_some() {
+        panic!("--sensitivity is not supported by the native engine");
+    } else if args.value_of("clusters").is_some() {
+        panic!("--clusters is not supported by the native engine");
+    } else if args.value_of("proposed").is_some() {
+        panic!("--proposed is not supported by the native engine");
+    } else if args.is_present("wet-season") {
+        panic!("--wet-season is not supported by the native engine");
+    } else if args.value_of("mode") == Some("travel") || args.value_of("friction").is_some() {
+        panic!("--mode travel is not supported by the native engine");
+    }
+
+    let rasters: Vec<String> = match args.value_of("raster") {
+        None => panic!("--raster is required by the native engine"),
+        Some(rasters) => rasters.split(',').map(|raster| raster.trim().to_string()).collect()
+    };
+
+    // Name written to the results, as there is no loaded dataset to take it from
+    let population = args.value_of("population").unwrap_or(args.value_of("raster").unwrap()).to_string();
+
+    let runs = Runs::new(&iso);
+    let mut recorder = match pool {
+        None => {
+            println!("ok - run is not recorded without --database");
+            None
+        },
+        Some(pool) => {
+            let mut db = pool.get().unwrap();
+
+            db.execute(format!("
+                CREATE SCHEMA IF NOT EXISTS country_{iso}
+            ", iso = &iso).as_str(), &[]).unwrap();
+
+            runs.create(&mut db);
+
+            let run = runs.start(&mut db, &serde_json::json!({
+                "network": runs::input(&master_src, &file_fingerprint(&master_src)),
+                "rasters": rasters.iter().map(|raster| {
+                    runs::input(raster, &file_fingerprint(raster))
+                }).collect::<Vec<serde_json::Value>>(),
+                "bounds": args.value_of("bounds").map(|bounds| {
+                    bounds.split(',').map(|bounds| bounds.trim()).map(|bounds| {
+                        runs::input(bounds, &file_fingerprint(bounds))
+                    }).collect::<Vec<serde_json::Value>>()
+                }),
+                "urban": args.value_of("urban").map(|urban| runs::input(urban, &file_fingerprint(urban))),
+                "country_boundary": args.value_of("country-boundary").map(|boundary| runs::input(boundary, &file_fingerprint(boundary)))
+            }), &serde_json::json!({
+                "engine": "native",
+                "population": &population,
+                "buffers": &buffers,
+                "urban_density": &urban_density,
+                "output": &output,
+                "grid": args.value_of("grid"),
+                "bounds_output": args.value_of("bounds-output")
+            }));
+
+            Some((db, run))
+        }
+    };
+
+    let country = match multipolygon(crate::pg::country::boundary(&iso, args.value_of("country-boundary")).geom.value) {
+        Ok(geom) => geom,
+        Err(err) => panic!("Invalid country geometry: {}", err)
+    };
+
+    let extent = match country.bounding_rect() {
+        None => panic!("Country geometry is empty"),
+        Some(bbox) => Extent {
+            minx: bbox.min().x,
+            miny: bbox.min().y,
+            maxx: bbox.max().x,
+            maxy: bbox.max().y
+        }
+    };
+
+    let mut subsets: Vec<Raster> = Vec::new();
+    for raster in rasters.iter() {
+        match Raster::open(raster, Some(&extent)) {
+            Ok(Some(subset)) => subsets.push(subset),
+            Ok(None) => (),
+            Err(err) => panic!("{}", err)
+        };
+    }
+
+    if subsets.is_empty() {
+        panic!("--raster files do not intersect {}", &iso);
+    }
+
+    let raster = match Raster::merge(subsets) {
+        Ok(raster) => raster,
+        Err(err) => panic!("--raster files could not be merged: {}", err)
+    };
+    println!("ok - read {}x{} raster subset", raster.cols, raster.rows);
+
+    let mut urban = vec![false; raster.cols * raster.rows];
+    if let Some(urban_src) = args.value_of("urban") {
+        let mut count = 0;
+        for poly in PolyStream::new(GeoStream::new(Some(urban_src.to_string())), Some(String::from("/tmp/urban_error.log"))) {
+            let geom = match multipolygon(geojson::Value::MultiPolygon(poly.geom)) {
+                Ok(geom) => geom,
+                Err(_) => continue
+            };
+
+            for (i, inside) in raster.mask(&geom).into_iter().enumerate() {
+                urban[i] = urban[i] || inside;
+            }
+
+            count += 1;
+        }
+
+        println!("ok - imported {} urban polygons", count);
+    }
+
+    let pixels = pixels(&raster, &raster.mask(&country), &urban, urban_density);
+    println!("ok - created {} population areas", pixels.len());
+    println!("ok - masked {} urban population areas", pixels.iter().filter(|px| px.urban).count());
+
+    let mut lines: Vec<geo::Line<f64>> = Vec::new();
+    for net in NetStream::new(GeoStream::new(Some(master_src)), Some(String::from("/tmp/master_error.log"))) {
+        for ln in net.geom.0.iter() {
+            lines.extend(ln.lines());
+        }
+    }
+    println!("ok - imported {} master line segments", lines.len());
+    timings.lap("import");
+
+    let areas: Option<Areas> = match (args.value_of("bounds"), args.value_of("grid")) {
+        (None, None) => None,
+        (Some(_), Some(_)) => panic!("--grid cannot be used with --bounds"),
+        (Some(bounds), None) => match Areas::load(bounds) {
+            Ok(areas) => Some(areas),
+            Err(err) => panic!("{}", err)
+        },
+        (None, Some(grid)) => match Cells::parse(grid).and_then(|cells| cells.areas(&country)) {
+            Ok(areas) => Some(areas),
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    // Leaf areas each pixel is within, along with the fraction of its population apportioned to each
+    let membership: Vec<Vec<(usize, f64)>> = match areas {
+        None => vec![Vec::new(); pixels.len()],
+        Some(ref areas) => {
+            // Residual areas have no geometry of their own, so are measured against their parent
+            let leaves: Vec<(usize, Option<geo::Rect<f64>>)> = areas.leaves().into_iter().map(|leaf| {
+                match areas.areas[leaf].parent {
+                    Some(parent) if areas.areas[leaf].residual => (leaf, areas.areas[parent].geom.bounding_rect()),
+                    _ => (leaf, areas.areas[leaf].geom.bounding_rect())
+                }
+            }).collect();
+
+            pixels.par_iter().map(|px| {
+                let cell = raster.cell(px.col, px.row);
+
+                let within: Vec<(usize, f64)> = leaves.iter().filter(|(_, bbox)| match bbox {
+                    Some(bbox) => bbox.intersects(&cell),
+                    None => false
+                }).map(|(leaf, _)| {
+                    let area = &areas.areas[*leaf];
+
+                    match area.parent {
+                        Some(parent) if area.residual => {
+                            let children: Vec<&geo::MultiPolygon<f64>> = areas.children(parent).into_iter().map(|child| {
+                                &areas.areas[child].geom
+                            }).collect();
+
+                            (*leaf, residual(&areas.areas[parent].geom, &children, &cell))
+                        },
+                        _ => (*leaf, fraction(&area.geom, &cell))
+                    }
+                }).filter(|(_, frac)| *frac > 0.0).collect();
+
+                apportion(within)
+            }).collect()
+        }
+    };
+
+    let mut wtr = csv::Writer::from_path(output).unwrap();
+    wtr.write_record(&HEADER).unwrap();
+
+    let mut bounds_output = match args.value_of("bounds-output") {
+        None => None,
+        Some(_) if areas.is_none() => panic!("--bounds-output requires --bounds or --grid"),
+        Some(path) => match Output::new(path) {
+            Ok(output) => Some(output),
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    let mut national_results: Vec<serde_json::Value> = Vec::with_capacity(buffers.len());
+    let mut results: Vec<(i64, Totals)> = Vec::new();
+    for buffer in buffers.iter() {
+        println!("ok - calculating {}m coverage", buffer);
+        let coverage = coverage(&raster, &pixels, &lines, *buffer);
+
+        let mut national = Totals::new("country", "", 0, "country", "all");
+        let mut leaves: BTreeMap<usize, Totals> = BTreeMap::new();
+
+        for (i, px) in pixels.iter().enumerate() {
+            add(&mut national, px, coverage[i], 1.0);
+
+            for (leaf, frac) in membership[i].iter() {
+                let area = leaves.entry(*leaf).or_insert_with(|| Totals::new("", "", 0, "", "all"));
+                add(area, px, coverage[i], *frac);
+            }
+        }
+
+        wtr.write_record(&national.as_record(&population, *buffer)).unwrap();
+        national_results.push(runs::result(*buffer, &national));
+        results.push((*buffer, national));
+
+        if let Some(ref areas) = areas {
+            for (i, area) in areas.rollup(leaves.into_iter().collect()) {
+                wtr.write_record(&area.as_record(&population, *buffer)).unwrap();
+
+                if let Some(ref mut output) = bounds_output {
+                    output.write(&area, &population, *buffer, &areas.areas[i].geom);
+                }
+
+                results.push((*buffer, area));
+            }
+        }
+    }
+    timings.lap("coverage");
+
+    if let Some(output) = bounds_output {
+        output.finish();
+    }
+
+    if let Some((ref mut db, run)) = recorder {
+        runs.results(db, run, &results);
+        runs.finish(db, run, &timings.to_json(), &serde_json::Value::from(national_results));
+        println!("ok - recorded run {}", run);
+    }
+}
+
+///
+/// Scale the fractions of a pixel within each area to sum to 1, so that the portion of a
+/// pixel outside of all areas (ie beyond the coastline) is not lost from the results
+///
+fn apportion(within: Vec<(usize, f64)>) -> Vec<(usize, f64)> {
+    let sum: f64 = within.iter().map(|(_, frac)| frac).sum();
+
+    within.into_iter().map(|(area, frac)| (area, frac / sum)).collect()
+}
+
+///
+/// Return the fraction of a cell within an area. Cells crossed by the boundary
+/// of the area are measured by sampling points across the cell
+///
+pub fn fraction(geom: &geo::MultiPolygon<f64>, cell: &geo::Rect<f64>) -> f64 {
+    let poly: geo::Polygon<f64> = cell.clone().into();
+
+    let edge = geom.0.iter().any(|area| {
+        std::iter::once(area.exterior()).chain(area.interiors().iter()).any(|ring| {
+            ring.lines().any(|line| line.intersects(&poly))
+        })
+    });
+
+    if !edge {
+        return if geom.contains(&cell.centroid()) { 1.0 } else { 0.0 };
+    }
+
+    let width = cell.max().x - cell.min().x;
+    let height = cell.max().y - cell.min().y;
+
+    let mut within = 0;
+    for sx in 0..SAMPLES {
+        for sy in 0..SAMPLES {
+            let sample = geo::Point::new(
+                cell.min().x + (sx as f64 + 0.5) * width / SAMPLES as f64,
+                cell.min().y + (sy as f64 + 0.5) * height / SAMPLES as f64
+            );
+
+            if geom.contains(&sample) {
+                within += 1;
+            }
+        }
+    }
+
+    within as f64 / (SAMPLES * SAMPLES) as f64
+}
+
+///
+/// Return the fraction of a cell within an area but outside of all of the given areas within it,
+/// measured by sampling points across the cell as for the boundary of an area
+///
+pub fn residual(geom: &geo::MultiPolygon<f64>, within: &Vec<&geo::MultiPolygon<f64>>, cell: &geo::Rect<f64>) -> f64 {
+    if fraction(geom, cell) == 0.0 {
+        return 0.0;
+    }
+
+    let width = cell.max().x - cell.min().x;
+    let height = cell.max().y - cell.min().y;
+
+    let mut outside = 0;
+    for sx in 0..SAMPLES {
+        for sy in 0..SAMPLES {
+            let sample = geo::Point::new(
+                cell.min().x + (sx as f64 + 0.5) * width / SAMPLES as f64,
+                cell.min().y + (sy as f64 + 0.5) * height / SAMPLES as f64
+            );
+
+            if geom.contains(&sample) && !within.iter().any(|area| area.contains(&sample)) {
+                outside += 1;
+            }
+        }
+    }
+
+    outside as f64 / (SAMPLES * SAMPLES) as f64
+}
+
+///
+/// Return the populated cells within the country mask, flagging those within an urban
+/// polygon or with a population density (people/km2) at or above the urban density
+///
+pub fn pixels(raster: &Raster, mask: &Vec<bool>, urban: &Vec<bool>, urban_density: Option<f64>) -> Vec<Pixel> {
+    let mut pixels = Vec::new();
+
+    for row in 0..raster.rows {
+        let (_, y) = raster.centre(0, row);
+        let area = raster.scale_x.abs() * LNG_METRES * y.to_radians().cos()
+            * raster.scale_y.abs() * LAT_METRES
+            / 1_000_000.0;
+
+        for col in 0..raster.cols {
+            let i = row * raster.cols + col;
+
+            let pop = match raster.get(col, row) {
+                Some(pop) if pop > 0.0 && mask[i] => pop,
+                _ => continue
+            };
+
+            let dense = match urban_density {
+                Some(density) => pop / area >= density,
+                None => false
+            };
+
+            pixels.push(Pixel {
+                col: col,
+                row: row,
+                pop: pop,
+                urban: urban[i] || dense
+            });
+        }
+    }
+
+    pixels
+}
+
+///
+/// Return the percentage (0-100) of each pixel within the given distance (metres) of a line
+///
+/// Distances are measured in a local equirectangular projection about the centre of each
+/// pixel. Pixels entirely within or beyond the distance of the network are resolved from
+/// the distance to their centre, others are sampled on a SAMPLES x SAMPLES grid
+///
+pub fn coverage(raster: &Raster, pixels: &Vec<Pixel>, lines: &Vec<geo::Line<f64>>, buffer: i64) -> Vec<i32> {
+    let buffer = buffer as f64;
+    let dlat = buffer / LAT_METRES;
+
+    // Bucket lines by the raster rows they are within the buffer distance of
+    let mut rows: Vec<Vec<usize>> = vec![Vec::new(); raster.rows];
+    for (i, line) in lines.iter().enumerate() {
+        let bbox = line.bounding_rect();
+
+        let start = ((raster.origin_y - bbox.max().y - dlat) / raster.scale_y.abs()).floor().max(0.0) as usize;
+        let end = ((raster.origin_y - bbox.min().y + dlat) / raster.scale_y.abs()).ceil().max(0.0).min(raster.rows as f64) as usize;
+
+        for row in start..end {
+            rows[row].push(i);
+        }
+    }
+
+    pixels.par_iter().map(|px| {
+        let (cx, cy) = raster.centre(px.col, px.row);
+
+        let kx = LNG_METRES * cy.to_radians().cos();
+        let ky = LAT_METRES;
+
+        let half_w = raster.scale_x.abs() * kx / 2.0;
+        let half_h = raster.scale_y.abs() * ky / 2.0;
+        let half_diag = half_w.hypot(half_h);
+
+        let project = |coord: geo::Coordinate<f64>| geo::Coordinate {
+            x: (coord.x - cx) * kx,
+            y: (coord.y - cy) * ky
+        };
+
+        let local: Vec<geo::Line<f64>> = rows[px.row].iter().map(|i| {
+            geo::Line::new(project(lines[*i].start), project(lines[*i].end))
+        }).filter(|line| {
+            let bbox = line.bounding_rect();
+
+            bbox.min().x <= half_w + buffer && bbox.max().x >= -half_w - buffer
+                && bbox.min().y <= half_h + buffer && bbox.max().y >= -half_h - buffer
+        }).collect();
+
+        let centre = geo::Point::new(0.0, 0.0);
+        let nearest = local.iter()
+            .map(|line| centre.euclidean_distance(line))
+            .fold(std::f64::INFINITY, f64::min);
+
+        if nearest + half_diag <= buffer {
+            return 100;
+        } else if nearest - half_diag > buffer {
+            return 0;
+        }
+
+        let mut covered = 0;
+        for sx in 0..SAMPLES {
+            for sy in 0..SAMPLES {
+                let sample = geo::Point::new(
+                    -half_w + (sx as f64 + 0.5) * half_w * 2.0 / SAMPLES as f64,
+                    -half_h + (sy as f64 + 0.5) * half_h * 2.0 / SAMPLES as f64
+                );
+
+                if local.iter().any(|line| sample.euclidean_distance(line) <= buffer) {
+                    covered += 1;
+                }
+            }
+        }
+
+        (covered * 100 / (SAMPLES * SAMPLES)) as i32
+    }).collect()
+}
+
+///
+/// Add the given fraction of a pixel to the totals of an area, only rural population
+/// contributes to the covered & uncovered figures as with the SQL engine
+///
+fn add(totals: &mut Totals, px: &Pixel, coverage: i32, frac: f64) {
+    let pop = px.pop * frac;
+
+    if px.urban {
+        totals.urban += pop;
+    } else {
+        totals.covered += pop * coverage as f64 / 100.0;
+        totals.uncovered += pop * (100 - coverage) as f64 / 100.0;
+        totals.rural += pop;
+    }
+
+    totals.total += pop;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster() -> Raster {
+        Raster {
+            origin_x: 0.0,
+            origin_y: 0.03,
+            scale_x: 0.01,
+            scale_y: -0.01,
+            cols: 3,
+            rows: 3,
+            values: vec![
+                1.0, 2.0, 3.0,
+                0.0, 5000.0, std::f32::NAN,
+                7.0, 8.0, 9.0
+            ]
+        }
+    }
+
+    #[test]
+    fn test_pixels() {
+        let raster = raster();
+
+        let px = pixels(&raster, &vec![true; 9], &vec![false; 9], Some(1000.0));
+        assert_eq!(px.len(), 7);
+        assert_eq!(px[3], Pixel {
+            col: 1,
+            row: 1,
+            pop: 5000.0,
+            urban: true
+        });
+        assert_eq!(px.iter().filter(|px| px.urban).count(), 1);
+
+        let mut mask = vec![true; 9];
+        mask[0] = false;
+        let mut urban = vec![false; 9];
+        urban[8] = true;
+
+        let px = pixels(&raster, &mask, &urban, None);
+        assert_eq!(px.len(), 6);
+        assert_eq!(px[0].col, 1);
+        assert_eq!(px.iter().filter(|px| px.urban).count(), 1);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let raster = raster();
+        let px = pixels(&raster, &vec![true; 9], &vec![false; 9], None);
+
+        // Vertical line through the centre of the middle column
+        let lines = vec![geo::Line::new(
+            geo::Coordinate { x: 0.015, y: 0.0 },
+            geo::Coordinate { x: 0.015, y: 0.03 }
+        )];
+
+        assert_eq!(coverage(&raster, &px, &lines, 2000), vec![100, 100, 100, 100, 100, 100, 100]);
+
+        // Samples 55m & 167m either side of the line, only the first pair being within 100m
+        assert_eq!(coverage(&raster, &px, &lines, 100), vec![0, 20, 0, 20, 0, 20, 0]);
+
+        assert_eq!(coverage(&raster, &px, &vec![], 2000), vec![0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut totals = Totals::new("country", "", 0, "country", "all");
+
+        add(&mut totals, &Pixel { col: 0, row: 0, pop: 10.0, urban: false }, 40, 1.0);
+        add(&mut totals, &Pixel { col: 1, row: 0, pop: 5.0, urban: true }, 100, 1.0);
+        add(&mut totals, &Pixel { col: 2, row: 0, pop: 20.0, urban: false }, 0, 0.5);
+
+        assert_eq!(totals.as_record("gpw_v4_2020", 2000), vec!["country", "", "0", "country", "gpw_v4_2020", "2000", "all", "4", "16", "20", "5", "25"]);
+    }
+
+    #[test]
+    fn test_apportion() {
+        assert_eq!(apportion(vec![(0, 0.25), (3, 0.5)]), vec![(0, 1.0 / 3.0), (3, 2.0 / 3.0)]);
+        assert_eq!(apportion(vec![(1, 1.0)]), vec![(1, 1.0)]);
+        assert_eq!(apportion(vec![]), vec![]);
+    }
+
+    #[test]
+    fn test_fraction() {
+        let area = geo::MultiPolygon(vec![geo::Polygon::new(geo::LineString::from(vec![
+            (0.0, 0.0),
+            (0.015, 0.0),
+            (0.015, 0.03),
+            (0.0, 0.03),
+            (0.0, 0.0)
+        ]), vec![])]);
+
+        let raster = raster();
+
+        assert_eq!(fraction(&area, &raster.cell(0, 0)), 1.0);
+        assert_eq!(fraction(&area, &raster.cell(1, 1)), 0.5);
+        assert_eq!(fraction(&area, &raster.cell(2, 2)), 0.0);
+    }
+
+    #[test]
+    fn test_residual() {
+        let rect = |max_x: f64| geo::MultiPolygon(vec![geo::Polygon::new(geo::LineString::from(vec![
+            (0.0, 0.0),
+            (max_x, 0.0),
+            (max_x, 0.03),
+            (0.0, 0.03),
+            (0.0, 0.0)
+        ]), vec![])]);
+
+        let parent = rect(0.015);
+        let child = rect(0.01);
+        let raster = raster();
+
+        assert_eq!(residual(&parent, &vec![&child], &raster.cell(0, 0)), 0.0);
+        assert_eq!(residual(&parent, &vec![&child], &raster.cell(1, 1)), 0.5);
+        assert_eq!(residual(&parent, &vec![&child], &raster.cell(2, 2)), 0.0);
+        assert_eq!(residual(&parent, &vec![], &raster.cell(1, 1)), fraction(&parent, &raster.cell(1, 1)));
+    }
+}
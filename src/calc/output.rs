@@ -0,0 +1,367 @@
+use std::io::Write;
+use std::convert::TryInto;
+use geo::algorithm::bounding_rect::BoundingRect;
+use super::Totals;
+
+///
+/// Writer of the results of each bounds polygon along with its geometry, as either
+/// line-delimited GeoJSON or a GeoPackage, chosen by the extension of the output file
+///
+pub enum Output {
+    GeoJson(std::io::BufWriter<std::fs::File>),
+    GeoPackage(GeoPackage)
+}
+
+impl Output {
+    pub fn new(path: &str) -> Result<Self, String> {
+        if path.to_lowercase().ends_with(".gpkg") {
+            Ok(Output::GeoPackage(GeoPackage::new(path)?))
+        } else {
+            match std::fs::File::create(path) {
+                Ok(file) => Ok(Output::GeoJson(std::io::BufWriter::new(file))),
+                Err(err) => Err(format!("Unable to create {}: {}", path, err))
+            }
+        }
+    }
+
+    pub fn write(&mut self, totals: &Totals, population: &str, buffer: i64, geom: &geo::MultiPolygon<f64>) {
+        match self {
+            Output::GeoJson(wtr) => {
+                writeln!(wtr, "{}", feature(totals, population, buffer, geom).to_string()).unwrap();
+            },
+            Output::GeoPackage(gpkg) => gpkg.insert(totals, population, buffer, geom)
+        };
+    }
+
+    pub fn finish(self) {
+        match self {
+            Output::GeoJson(mut wtr) => wtr.flush().unwrap(),
+            Output::GeoPackage(gpkg) => gpkg.finish()
+        };
+    }
+}
+
+///
+/// Return the results of a single area as a GeoJSON feature
+///
+fn feature(totals: &Totals, population: &str, buffer: i64, geom: &geo::MultiPolygon<f64>) -> geojson::Feature {
+    let mut props = serde_json::Map::new();
+    props.insert(String::from("id"), serde_json::json!(totals.id));
+    props.insert(String::from("parent"), serde_json::json!(totals.parent));
+    props.insert(String::from("level"), serde_json::json!(totals.level));
+    props.insert(String::from("name"), serde_json::json!(totals.name));
+    props.insert(String::from("population"), serde_json::json!(population));
+    props.insert(String::from("buffer"), serde_json::json!(buffer));
+    props.insert(String::from("class"), serde_json::json!(totals.class));
+    props.insert(String::from("covered"), serde_json::json!(totals.covered));
+    props.insert(String::from("uncovered"), serde_json::json!(totals.uncovered));
+    props.insert(String::from("rural"), serde_json::json!(totals.rural));
+    props.insert(String::from("urban"), serde_json::json!(totals.urban));
+    props.insert(String::from("total"), serde_json::json!(totals.total));
+    props.insert(String::from("rai"), serde_json::json!(totals.rai()));
+
+    geojson::Feature {
+        bbox: None,
+        geometry: Some(geojson::Geometry::new(geojson::Value::from(geom))),
+        id: None,
+        properties: Some(props),
+        foreign_members: None
+    }
+}
+
+///
+/// A minimal OGC GeoPackage containing a single rai feature table
+///
+pub struct GeoPackage {
+    conn: rusqlite::Connection,
+    extent: Option<geo::Rect<f64>>
+}
+
+impl GeoPackage {
+    pub fn new(path: &str) -> Result<Self, String> {
+        // A GeoPackage is a SQLite database, so replace rather than append to an existing file
+        if std::path::Path::new(path).exists() {
+            if let Err(err) = std::fs::remove_file(path) {
+                return Err(format!("Unable to replace {}: {}", path, err));
+            }
+        }
+
+        let conn = match rusqlite::Connection::open(path) {
+            Ok(conn) => conn,
+            Err(err) => { return Err(format!("Unable to create {}: {}", path, err)); }
+        };
+
+        match conn.execute_batch(r#"
+            PRAGMA application_id = 1196444487;
+            PRAGMA user_version = 10200;
+
+            CREATE TABLE gpkg_spatial_ref_sys (
+                srs_name                    TEXT NOT NULL,
+                srs_id                      INTEGER PRIMARY KEY,
+                organization                TEXT NOT NULL,
+                organization_coordsys_id    INTEGER NOT NULL,
+                definition                  TEXT NOT NULL,
+                description                 TEXT
+            );
+
+            INSERT INTO gpkg_spatial_ref_sys VALUES
+                ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'),
+                ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system'),
+                ('WGS 84 geodetic', 4326, 'EPSG', 4326, 'GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]', 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid');
+
+            CREATE TABLE gpkg_contents (
+                table_name  TEXT NOT NULL PRIMARY KEY,
+                data_type   TEXT NOT NULL,
+                identifier  TEXT UNIQUE,
+                description TEXT DEFAULT '',
+                last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+                min_x       DOUBLE,
+                min_y       DOUBLE,
+                max_x       DOUBLE,
+                max_y       DOUBLE,
+                srs_id      INTEGER REFERENCES gpkg_spatial_ref_sys(srs_id)
+            );
+
+            CREATE TABLE gpkg_geometry_columns (
+                table_name          TEXT NOT NULL REFERENCES gpkg_contents(table_name),
+                column_name         TEXT NOT NULL,
+                geometry_type_name  TEXT NOT NULL,
+                srs_id              INTEGER NOT NULL REFERENCES gpkg_spatial_ref_sys(srs_id),
+                z                   TINYINT NOT NULL,
+                m                   TINYINT NOT NULL,
+                PRIMARY KEY (table_name, column_name)
+            );
+
+            CREATE TABLE rai (
+                fid         INTEGER PRIMARY KEY AUTOINCREMENT,
+                geom        MULTIPOLYGON,
+                id          TEXT,
+                parent      TEXT,
+                level       INTEGER,
+                name        TEXT,
+                population  TEXT,
+                buffer      INTEGER,
+                class       TEXT,
+                covered     DOUBLE,
+                uncovered   DOUBLE,
+                rural       DOUBLE,
+                urban       DOUBLE,
+                total       DOUBLE,
+                rai         DOUBLE
+            );
+
+            INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id)
+                VALUES ('rai', 'features', 'rai', 4326);
+
+            INSERT INTO gpkg_geometry_columns
+                VALUES ('rai', 'geom', 'MULTIPOLYGON', 4326, 0, 0);
+
+            BEGIN;
+        "#) {
+            Ok(_) => Ok(GeoPackage {
+                conn: conn,
+                extent: None
+            }),
+            Err(err) => Err(format!("Unable to create {}: {}", path, err))
+        }
+    }
+
+    pub fn insert(&mut self, totals: &Totals, population: &str, buffer: i64, geom: &geo::MultiPolygon<f64>) {
+        if let Some(bbox) = geom.bounding_rect() {
+            self.extent = Some(match self.extent {
+                None => bbox,
+                Some(extent) => geo::Rect::new(
+                    geo::Coordinate { x: extent.min().x.min(bbox.min().x), y: extent.min().y.min(bbox.min().y) },
+                    geo::Coordinate { x: extent.max().x.max(bbox.max().x), y: extent.max().y.max(bbox.max().y) }
+                )
+            });
+        }
+
+        self.conn.execute("
+            INSERT INTO rai (
+                geom,
+                id,
+                parent,
+                level,
+                name,
+                population,
+                buffer,
+                class,
+                covered,
+                uncovered,
+                rural,
+                urban,
+                total,
+                rai
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+        ", rusqlite::params![
+            gpkg_geometry(geom),
+            totals.id,
+            totals.parent,
+            totals.level,
+            totals.name,
+            population,
+            buffer,
+            totals.class,
+            totals.covered,
+            totals.uncovered,
+            totals.rural,
+            totals.urban,
+            totals.total,
+            totals.rai()
+        ]).unwrap();
+    }
+
+    pub fn finish(self) {
+        if let Some(extent) = self.extent {
+            self.conn.execute("
+                UPDATE gpkg_contents
+                    SET
+                        min_x = ?1,
+                        min_y = ?2,
+                        max_x = ?3,
+                        max_y = ?4
+                    WHERE
+                        table_name = 'rai'
+            ", rusqlite::params![
+                extent.min().x,
+                extent.min().y,
+                extent.max().x,
+                extent.max().y
+            ]).unwrap();
+        }
+
+        self.conn.execute_batch("COMMIT").unwrap();
+    }
+}
+
+///
+/// Encode a MultiPolygon as a GeoPackage geometry blob, a header containing the
+/// SRID & XY envelope of the geometry, followed by the little endian WKB geometry
+///
+pub fn gpkg_geometry(geom: &geo::MultiPolygon<f64>) -> Vec<u8> {
+    let mut blob: Vec<u8> = vec![b'G', b'P', 0];
+
+    match geom.bounding_rect() {
+        None => {
+            // Little endian, no envelope, empty geometry
+            blob.push(0b0001_0001);
+            blob.extend_from_slice(&4326_i32.to_le_bytes());
+        },
+        Some(bbox) => {
+            // Little endian, [minx, maxx, miny, maxy] envelope
+            blob.push(0b0000_0011);
+            blob.extend_from_slice(&4326_i32.to_le_bytes());
+
+            for value in [bbox.min().x, bbox.max().x, bbox.min().y, bbox.max().y].iter() {
+                blob.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    };
+
+    blob.extend(wkb(geom));
+
+    blob
+}
+
+///
+/// Encode a MultiPolygon as little endian WKB
+///
+pub fn wkb(geom: &geo::MultiPolygon<f64>) -> Vec<u8> {
+    let mut wkb: Vec<u8> = vec![1];
+    wkb.extend_from_slice(&6_u32.to_le_bytes());
+    wkb.extend_from_slice(&(geom.0.len() as u32).to_le_bytes());
+
+    for poly in geom.0.iter() {
+        wkb.push(1);
+        wkb.extend_from_slice(&3_u32.to_le_bytes());
+        wkb.extend_from_slice(&((poly.interiors().len() + 1) as u32).to_le_bytes());
+
+        for ring in std::iter::once(poly.exterior()).chain(poly.interiors().iter()) {
+            wkb.extend_from_slice(&(ring.0.len() as u32).to_le_bytes());
+
+            for coord in ring.0.iter() {
+                wkb.extend_from_slice(&coord.x.to_le_bytes());
+                wkb.extend_from_slice(&coord.y.to_le_bytes());
+            }
+        }
+    }
+
+    wkb
+}
+
+///
+/// Convert a GeoJSON (Multi)Polygon into a geo MultiPolygon
+///
+pub fn multipolygon(value: geojson::Value) -> Result<geo::MultiPolygon<f64>, String> {
+    let value = match value {
+        geojson::Value::Polygon(py) => geojson::Value::MultiPolygon(vec![py]),
+        geojson::Value::MultiPolygon(mpy) => geojson::Value::MultiPolygon(mpy),
+        _ => { return Err(String::from("Geometry must be a (Multi)Polygon")); }
+    };
+
+    match value.try_into() {
+        Ok(geom) => Ok(geom),
+        Err(err) => Err(format!("Invalid GeoJSON geometry: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> geo::MultiPolygon<f64> {
+        geo::MultiPolygon(vec![geo::Polygon::new(geo::LineString::from(vec![
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (1.0, 2.0),
+            (0.0, 0.0)
+        ]), vec![])])
+    }
+
+    fn totals() -> Totals {
+        Totals {
+            id: String::from("Central/Asunción"),
+            parent: String::from("Central"),
+            level: 2,
+            name: String::from("Asunción"),
+            class: String::from("all"),
+            covered: 30.0,
+            uncovered: 10.0,
+            rural: 40.0,
+            urban: 60.0,
+            total: 100.0
+        }
+    }
+
+    #[test]
+    fn test_feature() {
+        let feat = feature(&totals(), "gpw_v4_2020", 2000, &triangle());
+
+        assert_eq!(feat.to_string(), r#"{"geometry":{"coordinates":[[[[0.0,0.0],[1.0,0.0],[1.0,2.0],[0.0,0.0]]]],"type":"MultiPolygon"},"properties":{"buffer":2000,"class":"all","covered":30.0,"id":"Central/Asunción","level":2,"name":"Asunción","parent":"Central","population":"gpw_v4_2020","rai":75.0,"rural":40.0,"total":100.0,"uncovered":10.0,"urban":60.0},"type":"Feature"}"#);
+    }
+
+    #[test]
+    fn test_wkb() {
+        let wkb = wkb(&triangle());
+
+        assert_eq!(wkb.len(), 1 + 4 + 4 + 1 + 4 + 4 + 4 + 4 * 16);
+        assert_eq!(&wkb[0..9], &[1, 6, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(&wkb[9..22], &[1, 3, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(&wkb[54..70], &[0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64]);
+    }
+
+    #[test]
+    fn test_gpkg_geometry() {
+        let blob = gpkg_geometry(&triangle());
+
+        assert_eq!(&blob[0..8], &[b'G', b'P', 0, 3, 230, 16, 0, 0]);
+        assert_eq!(&blob[8..16], &0.0_f64.to_le_bytes());
+        assert_eq!(&blob[16..24], &1.0_f64.to_le_bytes());
+        assert_eq!(&blob[24..32], &0.0_f64.to_le_bytes());
+        assert_eq!(&blob[32..40], &2.0_f64.to_le_bytes());
+        assert_eq!(&blob[40..], wkb(&triangle()).as_slice());
+
+        assert_eq!(gpkg_geometry(&geo::MultiPolygon(vec![]))[3], 0b0001_0001);
+    }
+}
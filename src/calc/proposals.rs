@@ -0,0 +1,324 @@
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use crate::pg::{Table, InputTable, Network};
+use crate::stream::{GeoStream, NetStream};
+use super::Totals;
+
+///
+/// A proposed road segment, along with the rural population it would newly cover
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Proposal {
+    pub id: String,
+    pub name: String,
+
+    /// Length of the segment (km)
+    pub length: f64,
+
+    /// Rural population newly covered by the segment
+    pub covered: f64
+}
+
+impl Proposal {
+    ///
+    /// Return the population newly covered per kilometre of road
+    ///
+    pub fn per_km(&self) -> Option<f64> {
+        if self.length > 0.0 {
+            Some(self.covered / self.length)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Return a row of the proposals report, with the national RAI before & after the segment is built
+    ///
+    fn as_record(&self, national: &Totals) -> Vec<String> {
+        let format = |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+
+        let before = national.rai();
+        let after = if national.rural > 0.0 {
+            Some((national.covered + self.covered) / national.rural * 100.0)
+        } else {
+            None
+        };
+
+        vec![
+            self.id.clone(),
+            self.name.clone(),
+            self.length.to_string(),
+            self.covered.to_string(),
+            format(self.per_km()),
+            format(before),
+            format(after),
+            format(after.and_then(|after| before.map(|before| after - before)))
+        ]
+    }
+}
+
+///
+/// Columns of the proposals report
+///
+const HEADER: [&str; 8] = [
+    "id",
+    "name",
+    "length km",
+    "newly covered population",
+    "newly covered population per km",
+    "rai before",
+    "rai after",
+    "rai change"
+];
+
+///
+/// Calculate the rural population newly covered at the first access distance by each
+/// proposed road segment on its own, and by all of them together, without modifying
+/// the master network
+///
+/// Segments are ranked by the population they newly cover per kilometre. As the areas
+/// covered by neighbouring segments may overlap, the gain of the whole package can be
+/// less than the sum of the gains of each segment
+///
+pub fn report(
+    pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>,
+    iso: &String,
+    buffer: i64,
+    source: &str,
+    national: &Totals,
+    path: &str
+) {
+    let mut db = pool.get().unwrap();
+
+    let proposed = Network::new(format!("country_{}.proposed", &iso));
+    proposed.create(&mut db);
+    proposed.input(&mut db, NetStream::new(
+        GeoStream::new(Some(source.to_string())),
+        Some(String::from("/tmp/proposed_error.log"))
+    ));
+    proposed.seq(&mut db);
+    proposed.index(&mut db);
+
+    let count = proposed.count(&mut db);
+    if count == 0 {
+        panic!("--proposed file must contain (Multi)LineString roads");
+    }
+    println!("ok - imported {} proposed roads", count);
+
+    db.execute(format!("
+        ALTER TABLE country_{iso}.proposed
+            ADD COLUMN geom_buff GEOMETRY(MultiPolygon, 4326)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        UPDATE country_{iso}.proposed
+            SET geom_buff = ST_Multi(ST_Buffer(geom::GEOGRAPHY, {buffer})::GEOMETRY)
+    ", iso = &iso, buffer = buffer).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX proposed_buff_idx
+            ON country_{iso}.proposed USING GIST (geom_buff)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    // The network is buffered into a table of its own, as the buffer of the master network is left
+    // over from the last access distance calculated & is not to be modified
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_proposed_network
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_proposed_network AS
+            SELECT
+                ST_Multi(ST_Buffer(geom::GEOGRAPHY, COALESCE(buffer, $1))::GEOMETRY) AS geom
+            FROM
+                country_{iso}.master
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_proposed_network_gix
+            ON country_{iso}.{iso}_proposed_network USING GIST (geom)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    // Only rural pixels that are not fully covered & are within reach of a proposed road can gain coverage
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_proposed_px
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_proposed_px AS
+            SELECT
+                px.id,
+                px.pop,
+                px.geom,
+                (
+                    SELECT
+                        ST_Intersection(ST_Union(network.geom), px.geom)
+                    FROM
+                        country_{iso}.{iso}_proposed_network AS network
+                    WHERE
+                        ST_Intersects(network.geom, px.geom)
+                ) AS network
+            FROM
+                country_{iso}.{iso}_geom AS px
+                    INNER JOIN country_{iso}.{iso}_coverage AS cov
+                        ON px.id = cov.id
+            WHERE
+                cov.buffer = $1
+                AND cov.class IS NULL
+                AND cov.coverage < 100
+                AND NOT px.urban
+                AND EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        country_{iso}.proposed
+                    WHERE
+                        ST_Intersects(proposed.geom_buff, px.geom)
+                )
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_proposed_px_gix
+            ON country_{iso}.{iso}_proposed_px USING GIST (geom)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    let segments: Vec<(i64, String, String, f64)> = match db.query(format!("
+        SELECT
+            id,
+            COALESCE(props->>'id', id::TEXT),
+            COALESCE(props->>'name', ''),
+            ST_Length(geom::GEOGRAPHY) / 1000
+        FROM
+            country_{iso}.proposed
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| (row.get(0), row.get(1), row.get(2), row.get(3))).collect()
+    };
+
+    println!("ok - calculating coverage of each proposed road");
+    let pb = ProgressBar::new(segments.len() as u64);
+
+    let mut proposals: Vec<Proposal> = segments.par_iter().map(|(seq, id, name, length)| {
+        let covered = covered(&mut pool.get().unwrap(), iso, Some(*seq));
+        pb.inc(1);
+
+        Proposal {
+            id: id.to_string(),
+            name: name.to_string(),
+            length: *length,
+            covered: covered
+        }
+    }).collect();
+    pb.finish();
+
+    proposals = rank(proposals);
+
+    proposals.push(Proposal {
+        id: String::from("package"),
+        name: String::from("all proposed roads"),
+        length: segments.iter().map(|segment| segment.3).sum(),
+        covered: covered(&mut db, iso, None)
+    });
+
+    let mut wtr = csv::Writer::from_path(path).unwrap();
+    wtr.write_record(&HEADER).unwrap();
+
+    for proposal in proposals.iter() {
+        wtr.write_record(&proposal.as_record(national)).unwrap();
+    }
+
+    println!("ok - wrote coverage of {} proposed roads", segments.len());
+}
+
+///
+/// Return the rural population newly covered by a single proposed road, or all of them
+///
+fn covered(db: &mut postgres::Client, iso: &String, id: Option<i64>) -> f64 {
+    match db.query(format!("
+        SELECT
+            COALESCE(SUM(px.pop * (px.after - px.before) * 0.01), 0.0)
+        FROM (
+            SELECT
+                px.pop,
+                ROUND(LEAST(COALESCE(ST_Area(px.network), 0.0) / ST_Area(px.geom), 1) * 100) AS before,
+                ROUND(LEAST(ST_Area(ST_Intersection(
+                    CASE
+                        WHEN px.network IS NULL THEN proposed.geom
+                        ELSE ST_Union(px.network, proposed.geom)
+                    END,
+                    px.geom
+                )) / ST_Area(px.geom), 1) * 100) AS after
+            FROM
+                country_{iso}.{iso}_proposed_px AS px,
+                LATERAL (
+                    SELECT
+                        ST_Union(proposed.geom_buff) AS geom
+                    FROM
+                        country_{iso}.proposed
+                    WHERE
+                        ($1::BIGINT IS NULL OR proposed.id = $1)
+                        AND ST_Intersects(proposed.geom_buff, px.geom)
+                ) AS proposed
+            WHERE
+                proposed.geom IS NOT NULL
+        ) AS px
+    ", iso = &iso).as_str(), &[&id]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.get(0).unwrap().get(0)
+    }
+}
+
+///
+/// Order proposals by the population newly covered per kilometre, with those of no length last
+///
+fn rank(mut proposals: Vec<Proposal>) -> Vec<Proposal> {
+    proposals.sort_by(|a, b| {
+        let a = a.per_km().unwrap_or(std::f64::NEG_INFINITY);
+        let b = b.per_km().unwrap_or(std::f64::NEG_INFINITY);
+
+        b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    proposals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(id: &str, length: f64, covered: f64) -> Proposal {
+        Proposal {
+            id: id.to_string(),
+            name: String::new(),
+            length: length,
+            covered: covered
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let ranked: Vec<String> = rank(vec![
+            proposal("1", 10.0, 100.0),
+            proposal("2", 0.0, 50.0),
+            proposal("3", 2.0, 80.0),
+            proposal("4", 4.0, 0.0)
+        ]).into_iter().map(|proposal| proposal.id).collect();
+
+        assert_eq!(ranked, vec!["3", "1", "4", "2"]);
+    }
+
+    #[test]
+    fn test_as_record() {
+        let mut national = Totals::new("country", "", 0, "country", "all");
+        national.covered = 400.0;
+        national.rural = 1000.0;
+
+        assert_eq!(proposal("PR-1", 2.5, 100.0).as_record(&national), vec![
+            "PR-1", "", "2.5", "100", "40", "40", "50", "10"
+        ]);
+
+        assert_eq!(proposal("PR-2", 0.0, 0.0).as_record(&national), vec![
+            "PR-2", "", "0", "0", "", "40", "40", "0"
+        ]);
+    }
+}
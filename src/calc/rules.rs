@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use postgres::Client;
+
+///
+/// A rule set controlling how each road in the master network contributes to coverage
+///
+/// Rules are read from a JSON file in the form:
+///
+/// {
+///     "default": { "weight": 1.0 },
+///     "highway": {
+///         "primary": { "buffer": 5000 },
+///         "residential": { "buffer": 1000, "weight": 0.5 }
+///     },
+///     "inferred_surface": 0.75
+/// }
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Rules {
+    /// Rule applied to any road class that does not have an explicit rule
+    #[serde(default)]
+    pub default: Rule,
+
+    /// Rules keyed by the value of the `highway` property
+    #[serde(default)]
+    pub highway: HashMap<String, Rule>,
+
+    /// Weight multiplier for roads whose surface was inferred as opposed to tagged
+    #[serde(default = "Rules::full_weight")]
+    pub inferred_surface: f64
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Rule {
+    /// Buffer distance (metres) overriding the access distance of the run
+    pub buffer: Option<i64>,
+
+    /// Confidence (0-1) that the road provides all-season access
+    pub weight: Option<f64>
+}
+
+impl Rules {
+    pub fn new(rules: &str) -> Result<Self, String> {
+        let rules: Rules = match serde_json::from_str(rules) {
+            Ok(rules) => rules,
+            Err(err) => { return Err(format!("Invalid rules: {}", err)); }
+        };
+
+        for (class, rule) in rules.highway.iter().chain(vec![(&String::from("default"), &rules.default)]) {
+            match rule.buffer {
+                Some(buffer) if buffer <= 0 => {
+                    return Err(format!("{} rule buffer must be a positive integer", class));
+                },
+                _ => ()
+            };
+
+            match rule.weight {
+                Some(weight) if weight < 0.0 || weight > 1.0 => {
+                    return Err(format!("{} rule weight must be between 0 and 1", class));
+                },
+                _ => ()
+            };
+        }
+
+        if rules.inferred_surface < 0.0 || rules.inferred_surface > 1.0 {
+            return Err(String::from("inferred_surface weight must be between 0 and 1"));
+        }
+
+        Ok(rules)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(rules) => Rules::new(&rules),
+            Err(err) => Err(format!("Unable to read rules file: {}", err))
+        }
+    }
+
+    fn full_weight() -> f64 {
+        1.0
+    }
+
+    ///
+    /// Return the rule for a given road class, falling back to
+    /// the default rule for any unset values
+    ///
+    pub fn rule(&self, class: &str) -> Rule {
+        match self.highway.get(class) {
+            None => self.default.clone(),
+            Some(rule) => Rule {
+                buffer: rule.buffer.or(self.default.buffer),
+                weight: rule.weight.or(self.default.weight)
+            }
+        }
+    }
+
+    ///
+    /// Populate the class, buffer & weight columns of a network table
+    ///
+    pub fn apply(&self, db: &mut Client, table: &str) {
+        db.execute(format!("
+            UPDATE {table}
+                SET
+                    class = COALESCE(props->>'highway', 'unknown'),
+                    buffer = $1,
+                    weight = $2
+        ", table = table).as_str(), &[
+            &self.default.buffer,
+            &self.default.weight.unwrap_or(1.0)
+        ]).unwrap();
+
+        for class in self.highway.keys() {
+            let rule = self.rule(class);
+
+            db.execute(format!("
+                UPDATE {table}
+                    SET
+                        buffer = $2,
+                        weight = $3
+                    WHERE
+                        class = $1
+            ", table = table).as_str(), &[
+                &class,
+                &rule.buffer,
+                &rule.weight.unwrap_or(1.0)
+            ]).unwrap();
+        }
+
+        db.execute(format!("
+            UPDATE {table}
+                SET
+                    weight = weight * $1
+                WHERE
+                    props->>'surface' IS NULL
+        ", table = table).as_str(), &[&self.inferred_surface]).unwrap();
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            default: Rule::default(),
+            highway: HashMap::new(),
+            inferred_surface: 1.0
+        }
+    }
+}
+
+///
+/// Calculate the weighted fraction of a pixel that is covered given the area
+/// of the pixel covered by roads of at least a given weight
+///
+/// Each tier is a tuple of (weight, area covered by roads with >= weight),
+/// the area gained at each successively lower weight is credited at that weight
+///
+pub fn weighted(mut tiers: Vec<(f64, f64)>, area: f64) -> f64 {
+    if area <= 0.0 {
+        return 0.0;
+    }
+
+    tiers.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+    let mut covered = 0.0;
+    let mut previous = 0.0;
+    for (weight, tier_area) in tiers {
+        let tier_area = tier_area.min(area);
+
+        if tier_area > previous {
+            covered += weight * (tier_area - previous);
+            previous = tier_area;
+        }
+    }
+
+    covered / area
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let rules = Rules::new(r#"{
+            "default": { "weight": 0.9 },
+            "highway": {
+                "primary": { "buffer": 5000 },
+                "residential": { "buffer": 1000, "weight": 0.5 }
+            },
+            "inferred_surface": 0.75
+        }"#).unwrap();
+
+        assert_eq!(rules.rule("primary"), Rule {
+            buffer: Some(5000),
+            weight: Some(0.9)
+        });
+
+        assert_eq!(rules.rule("residential"), Rule {
+            buffer: Some(1000),
+            weight: Some(0.5)
+        });
+
+        assert_eq!(rules.rule("track"), Rule {
+            buffer: None,
+            weight: Some(0.9)
+        });
+
+        assert_eq!(rules.inferred_surface, 0.75);
+
+        assert_eq!(Rules::new("{}").unwrap(), Rules::default());
+
+        assert_eq!(Rules::new(r#"{ "highway": { "primary": { "weight": 2 } } }"#), Err(String::from("primary rule weight must be between 0 and 1")));
+        assert_eq!(Rules::new(r#"{ "default": { "buffer": 0 } }"#), Err(String::from("default rule buffer must be a positive integer")));
+    }
+
+    #[test]
+    fn test_weighted() {
+        assert_eq!(weighted(vec![], 1.0), 0.0);
+        assert_eq!(weighted(vec![(1.0, 0.5)], 1.0), 0.5);
+        assert_eq!(weighted(vec![(1.0, 0.5)], 0.0), 0.0);
+
+        // Lower weighted roads only contribute the area not already covered
+        assert_eq!(weighted(vec![(0.5, 1.0), (1.0, 0.5)], 1.0), 0.75);
+
+        // Tiers that add no new area contribute nothing
+        assert_eq!(weighted(vec![(1.0, 0.5), (0.5, 0.5)], 1.0), 0.5);
+
+        // Coverage can never exceed the area of the pixel
+        assert_eq!(weighted(vec![(1.0, 2.0)], 1.0), 1.0);
+    }
+}
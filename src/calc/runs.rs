@@ -0,0 +1,427 @@
+use std::time::Instant;
+use postgres::Client;
+use postgres::error::SqlState;
+use super::Totals;
+
+///
+/// Records every calc run of a country within the country schema, along with the
+/// inputs, parameters, timings & national result of the run, so that results can be
+/// audited & reproduced
+///
+/// A run is recorded when it starts, so that failed runs remain visible without a
+/// finished time
+///
+/// The results of each area & the coverage of each pixel are also kept with each run,
+/// so that any two runs can later be compared by the diff module. The geometry & population
+/// of each pixel is only kept once per population grid, rather than with every run
+///
+pub struct Runs {
+    iso: String,
+    name: String
+}
+
+impl Runs {
+    pub fn new(iso: &str) -> Self {
+        Runs {
+            iso: iso.to_string(),
+            name: format!("country_{}.runs", iso)
+        }
+    }
+
+    pub fn create(&self, db: &mut Client) {
+        db.execute(format!("
+            CREATE TABLE IF NOT EXISTS {} (
+                id          SERIAL PRIMARY KEY,
+                version     TEXT NOT NULL,
+                inputs      JSONB NOT NULL,
+                parameters  JSONB NOT NULL,
+                timings     JSONB,
+                result      JSONB,
+                started     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                finished    TIMESTAMPTZ,
+                grid        TEXT
+            )
+        ", &self.name).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            CREATE TABLE IF NOT EXISTS country_{iso}.run_results (
+                run         INT NOT NULL,
+                ord         INT NOT NULL,
+                buffer      BIGINT NOT NULL,
+                id          TEXT NOT NULL,
+                parent      TEXT NOT NULL,
+                level       BIGINT NOT NULL,
+                name        TEXT NOT NULL,
+                class       TEXT NOT NULL,
+                covered     FLOAT8 NOT NULL,
+                uncovered   FLOAT8 NOT NULL,
+                rural       FLOAT8 NOT NULL,
+                urban       FLOAT8 NOT NULL,
+                total       FLOAT8 NOT NULL
+            )
+        ", iso = &self.iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            CREATE TABLE IF NOT EXISTS country_{iso}.run_pixels (
+                grid        TEXT NOT NULL,
+                id          BIGINT NOT NULL,
+                pop         FLOAT8 NOT NULL,
+                urban       BOOLEAN NOT NULL,
+                geom        GEOMETRY,
+                PRIMARY KEY (grid, id)
+            )
+        ", iso = &self.iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            CREATE INDEX IF NOT EXISTS run_pixels_gix
+                ON country_{iso}.run_pixels USING GIST (geom)
+        ", iso = &self.iso).as_str(), &[]).unwrap();
+
+        db.execute(format!("
+            CREATE TABLE IF NOT EXISTS country_{iso}.run_coverage (
+                run         INT NOT NULL,
+                id          BIGINT NOT NULL,
+                buffer      BIGINT NOT NULL,
+                coverage    INT NOT NULL,
+                PRIMARY KEY (run, buffer, id)
+            )
+        ", iso = &self.iso).as_str(), &[]).unwrap();
+    }
+
+    ///
+    /// Return the ids of the runs that finished, oldest first, or none if no run
+    /// of the country has been recorded
+    ///
+    pub fn finished(&self, db: &mut Client) -> Vec<i32> {
+        match db.query(format!("
+            SELECT
+                id
+            FROM
+                {}
+            WHERE
+                finished IS NOT NULL
+            ORDER BY
+                id
+        ", &self.name).as_str(), &[]) {
+            Err(ref err) if err.code() == Some(&SqlState::UNDEFINED_TABLE) => Vec::new(),
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.iter().map(|row| row.get(0)).collect()
+        }
+    }
+
+    ///
+    /// Record the start of a run, returning its id
+    ///
+    pub fn start(&self, db: &mut Client, inputs: &serde_json::Value, parameters: &serde_json::Value) -> i32 {
+        match db.query(format!("
+            INSERT INTO {} (
+                version,
+                inputs,
+                parameters
+            ) VALUES (
+                $1,
+                $2,
+                $3
+            ) RETURNING id
+        ", &self.name).as_str(), &[&env!("CARGO_PKG_VERSION"), inputs, parameters]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.get(0).unwrap().get(0)
+        }
+    }
+
+    pub fn finish(&self, db: &mut Client, id: i32, timings: &serde_json::Value, result: &serde_json::Value) {
+        db.execute(format!("
+            UPDATE {}
+                SET
+                    timings = $2,
+                    result = $3,
+                    finished = NOW()
+                WHERE
+                    id = $1
+        ", &self.name).as_str(), &[&id, timings, result]).unwrap();
+    }
+
+    ///
+    /// Keep the results of each area & the network coverage of each pixel of a run
+    ///
+    /// Pixels are keyed by their id within the given population grid, whose pixels are
+    /// only kept by the first run to use it
+    ///
+    pub fn snapshot(&self, db: &mut Client, id: i32, grid: &str, results: &Vec<(i64, Totals)>) {
+        self.results(db, id, results);
+
+        db.execute(format!("
+            UPDATE {}
+                SET grid = $2
+                WHERE id = $1
+        ", &self.name).as_str(), &[&id, &grid]).unwrap();
+
+        db.execute(format!("
+            INSERT INTO country_{iso}.run_pixels (
+                grid,
+                id,
+                pop,
+                urban,
+                geom
+            ) SELECT
+                $1,
+                px.id,
+                px.pop,
+                px.urban,
+                px.geom
+            FROM
+                country_{iso}.{iso}_geom AS px
+            WHERE
+                NOT EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        country_{iso}.run_pixels
+                    WHERE
+                        grid = $1
+                )
+        ", iso = &self.iso).as_str(), &[&grid]).unwrap();
+
+        db.execute(format!("
+            INSERT INTO country_{iso}.run_coverage (
+                run,
+                id,
+                buffer,
+                coverage
+            ) SELECT
+                $1,
+                cov.id,
+                cov.buffer,
+                cov.coverage
+            FROM
+                country_{iso}.{iso}_coverage AS cov
+            WHERE
+                cov.class IS NULL
+        ", iso = &self.iso).as_str(), &[&id]).unwrap();
+    }
+
+    ///
+    /// Keep the results of each area of a run, without the coverage of each pixel, as for
+    /// runs of the native engine
+    ///
+    pub fn results(&self, db: &mut Client, id: i32, results: &Vec<(i64, Totals)>) {
+        for (ord, (buffer, totals)) in results.iter().enumerate() {
+            db.execute(format!("
+                INSERT INTO country_{iso}.run_results (
+                    run,
+                    ord,
+                    buffer,
+                    id,
+                    parent,
+                    level,
+                    name,
+                    class,
+                    covered,
+                    uncovered,
+                    rural,
+                    urban,
+                    total
+                ) VALUES (
+                    $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13
+                )
+            ", iso = &self.iso).as_str(), &[
+                &id,
+                &(ord as i32),
+                buffer,
+                &totals.id,
+                &totals.parent,
+                &totals.level,
+                &totals.name,
+                &totals.class,
+                &totals.covered,
+                &totals.uncovered,
+                &totals.rural,
+                &totals.urban,
+                &totals.total
+            ]).unwrap();
+        }
+    }
+
+    ///
+    /// Return a one line summary of the most recent run, if any, including when no
+    /// run of the country has been recorded
+    ///
+    pub fn latest(&self, db: &mut Client) -> Option<String> {
+        match db.query(format!("
+            SELECT
+                to_char(started, 'YYYY-MM-DD HH24:MI:SS TZ'),
+                finished IS NOT NULL,
+                inputs->'network'->>'path',
+                parameters->>'population',
+                result
+            FROM
+                {}
+            ORDER BY
+                id DESC
+            LIMIT 1
+        ", &self.name).as_str(), &[]) {
+            Err(ref err) if err.code() == Some(&SqlState::UNDEFINED_TABLE) => None,
+            Err(err) => panic!("{}", err),
+            Ok(res) => match res.get(0) {
+                None => None,
+                Some(row) => {
+                    let finished: bool = row.get(1);
+                    let network: Option<String> = row.get(2);
+                    let population: Option<String> = row.get(3);
+                    let result: Option<serde_json::Value> = row.get(4);
+
+                    Some(summary(
+                        row.get(0),
+                        finished,
+                        &network.unwrap_or_default(),
+                        &population.unwrap_or_default(),
+                        result.as_ref()
+                    ))
+                }
+            }
+        }
+    }
+}
+
+///
+/// Return a one line summary of a run
+///
+fn summary(started: String, finished: bool, network: &str, population: &str, result: Option<&serde_json::Value>) -> String {
+    let rai: Vec<String> = match result {
+        Some(serde_json::Value::Array(buffers)) => buffers.iter().map(|buffer| {
+            match (buffer.get("buffer"), buffer.get("rai")) {
+                (Some(distance), Some(serde_json::Value::Number(rai))) => {
+                    format!("{}m RAI {:.1}%", distance, rai.as_f64().unwrap_or(0.0))
+                },
+                (Some(distance), _) => format!("{}m RAI -", distance),
+                _ => String::from("-")
+            }
+        }).collect(),
+        _ => Vec::new()
+    };
+
+    if !finished {
+        format!("{} {} {} (incomplete)", started, network, population)
+    } else {
+        format!("{} {} {} {}", started, network, population, rai.join(", "))
+    }
+}
+
+///
+/// Return the path & checksum of an input file
+///
+pub fn input(path: &str, checksum: &str) -> serde_json::Value {
+    serde_json::json!({
+        "path": path,
+        "sha256": checksum
+    })
+}
+
+///
+/// Return the national result of a single access distance
+///
+pub fn result(buffer: i64, totals: &Totals) -> serde_json::Value {
+    serde_json::json!({
+        "buffer": buffer,
+        "covered": totals.covered,
+        "uncovered": totals.uncovered,
+        "rural": totals.rural,
+        "urban": totals.urban,
+        "total": totals.total,
+        "rai": totals.rai()
+    })
+}
+
+///
+/// Wall clock time taken by each stage of a run
+///
+pub struct Timings {
+    start: Instant,
+    last: Instant,
+    laps: serde_json::Map<String, serde_json::Value>
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        Timings {
+            start: now,
+            last: now,
+            laps: serde_json::Map::new()
+        }
+    }
+
+    ///
+    /// Record the seconds taken by a stage since the previous stage finished
+    ///
+    pub fn lap(&mut self, stage: &str) {
+        let now = Instant::now();
+        self.laps.insert(stage.to_string(), serde_json::json!(now.duration_since(self.last).as_secs_f64()));
+        self.last = now;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut laps = self.laps.clone();
+        laps.insert(String::from("total"), serde_json::json!(self.start.elapsed().as_secs_f64()));
+
+        serde_json::Value::Object(laps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let result = serde_json::json!([{
+            "buffer": 2000,
+            "rai": 61.234
+        }, {
+            "buffer": 5000,
+            "rai": null
+        }]);
+
+        assert_eq!(
+            summary(String::from("2020-05-01 12:00:00 UTC"), true, "py.geojsonld", "gpw_v4_2020", Some(&result)),
+            "2020-05-01 12:00:00 UTC py.geojsonld gpw_v4_2020 2000m RAI 61.2%, 5000m RAI -"
+        );
+
+        assert_eq!(
+            summary(String::from("2020-05-01 12:00:00 UTC"), false, "py.geojsonld", "gpw_v4_2020", None),
+            "2020-05-01 12:00:00 UTC py.geojsonld gpw_v4_2020 (incomplete)"
+        );
+    }
+
+    #[test]
+    fn test_result() {
+        let mut totals = Totals::new("country", "", 0, "country", "all");
+        totals.covered = 30.0;
+        totals.uncovered = 10.0;
+        totals.rural = 40.0;
+        totals.total = 40.0;
+
+        assert_eq!(result(2000, &totals), serde_json::json!({
+            "buffer": 2000,
+            "covered": 30.0,
+            "uncovered": 10.0,
+            "rural": 40.0,
+            "urban": 0.0,
+            "total": 40.0,
+            "rai": 75.0
+        }));
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut timings = Timings::new();
+        timings.lap("raster");
+        timings.lap("network");
+
+        let json = timings.to_json();
+        assert!(json["raster"].is_number());
+        assert!(json["network"].is_number());
+        assert!(json["total"].as_f64().unwrap() >= json["raster"].as_f64().unwrap());
+    }
+}
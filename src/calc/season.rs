@@ -0,0 +1,106 @@
+use super::overlay;
+
+/// Class of the results of the network as available during the wet season
+pub const WET_SEASON: &str = "wet season";
+
+///
+/// SQL condition of the roads of the master network that are closed during the wet season,
+/// those tagged as only seasonally passable, crossing a ford, or prone to flooding
+///
+/// Roads tagged as only passable during the wet season (seasonal=wet_season) remain open
+///
+pub const CLOSED: &str = "
+    COALESCE(props->>'seasonal', 'no') NOT IN ('no', 'wet_season')
+    OR COALESCE(props->>'ford', 'no') != 'no'
+    OR COALESCE(props->>'flood_prone', 'no') = 'yes'
+";
+
+///
+/// Calculate the coverage of each pixel by the network as available during the wet season,
+/// recording it in {iso}_coverage under the wet season class
+///
+/// Roads closed during the wet season are removed, and the portion of all other roads within
+/// a flood polygon is cut out, a raster tile at a time as with the coverage of the network itself
+///
+pub fn coverage(pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, iso: &String, buffer: i64) {
+    let mut db = pool.get().unwrap();
+
+    db.execute(format!("
+        DELETE
+            FROM
+                country_{iso}.{iso}_coverage
+            WHERE
+                buffer = $1
+                AND class = $2
+    ", iso = &iso).as_str(), &[&buffer, &WET_SEASON]).unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_wet
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_wet AS
+            SELECT
+                ST_Multi(ST_Buffer(road.geom::GEOGRAPHY, COALESCE(road.buffer, $1))::GEOMETRY) AS geom
+            FROM (
+                SELECT
+                    master.buffer,
+                    CASE
+                        WHEN flood.geom IS NULL THEN master.geom
+                        ELSE ST_Difference(master.geom, flood.geom)
+                    END AS geom
+                FROM
+                    country_{iso}.master
+                        LEFT JOIN LATERAL (
+                            SELECT
+                                ST_Union(flood.geom) AS geom
+                            FROM
+                                country_{iso}.flood
+                            WHERE
+                                ST_Intersects(flood.geom, master.geom)
+                        ) AS flood ON TRUE
+                WHERE
+                    NOT ({closed})
+            ) AS road
+            WHERE
+                NOT ST_IsEmpty(road.geom)
+    ", iso = &iso, closed = CLOSED).as_str(), &[&buffer]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_wet_gix
+            ON country_{iso}.{iso}_wet USING GIST (geom)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    println!("ok - buffered wet season network by {}m", buffer);
+
+    let rids: Vec<i32> = match db.query(format!("
+        SELECT DISTINCT
+            rid
+        FROM
+            country_{iso}.{iso}_geom
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| row.get(0)).collect()
+    };
+
+    overlay(pool, iso, &format!("country_{}.{}_wet", &iso, &iso), "geom", &rids, "");
+
+    db.execute(format!("
+        INSERT INTO country_{iso}.{iso}_coverage (
+            id,
+            buffer,
+            class,
+            coverage
+        ) SELECT
+            px.id,
+            $1,
+            $2,
+            COALESCE(overlay.coverage, 0)
+        FROM
+            country_{iso}.{iso}_geom AS px
+                LEFT JOIN country_{iso}.{iso}_overlay AS overlay
+                    ON px.id = overlay.id
+    ", iso = &iso).as_str(), &[&buffer, &WET_SEASON]).unwrap();
+
+    println!("ok - done calculating {}m wet season coverage", buffer);
+}
@@ -0,0 +1,329 @@
+use super::{Totals, TOTALS, overlay};
+use super::admin::Areas;
+
+/// Fraction by which the access distance is shortened & lengthened
+const BUFFER_SPREAD: f64 = 0.25;
+
+/// Difference from 1 below which a scale is taken to leave the access distance as is
+const SCALE_TOLERANCE: f64 = 1e-9;
+
+///
+/// A perturbation of the inputs of a calc run
+///
+#[derive(Debug, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+
+    /// Multiplier of the access distance of each road
+    scale: f64,
+
+    /// SQL condition of the roads of the master network included in the scenario
+    filter: &'static str
+}
+
+impl Scenario {
+    ///
+    /// Return whether the access distance is left as is, the scenario only excluding roads
+    ///
+    pub fn unscaled(&self) -> bool {
+        (self.scale - 1.0).abs() < SCALE_TOLERANCE
+    }
+}
+
+///
+/// Return the scenarios the RAI is recalculated under
+///
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: format!("buffer -{}%", BUFFER_SPREAD * 100.0),
+            scale: 1.0 - BUFFER_SPREAD,
+            filter: "TRUE"
+        },
+        Scenario {
+            name: format!("buffer +{}%", BUFFER_SPREAD * 100.0),
+            scale: 1.0 + BUFFER_SPREAD,
+            filter: "TRUE"
+        },
+        Scenario {
+            name: String::from("tagged surfaces only"),
+            scale: 1.0,
+            filter: "props->>'surface' IS NOT NULL"
+        },
+        Scenario {
+            name: String::from("without added roads"),
+            scale: 1.0,
+            filter: "NOT COALESCE((props->>'added')::BOOLEAN, FALSE)"
+        }
+    ]
+}
+
+///
+/// Recalculate coverage of the first access distance under each scenario, writing
+/// the RAI of each scenario along with the range across all scenarios per area
+///
+pub fn report(
+    pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>,
+    iso: &String,
+    buffer: i64,
+    population: &str,
+    areas: Option<&Areas>,
+    path: &str
+) {
+    let mut db = pool.get().unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_sensitivity
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_sensitivity AS
+            SELECT
+                cov.id,
+                'base'::TEXT AS scenario,
+                cov.coverage
+            FROM
+                country_{iso}.{iso}_coverage AS cov
+            WHERE
+                cov.buffer = $1
+                AND cov.class IS NULL
+    ", iso = &iso).as_str(), &[&buffer]).unwrap();
+
+    let mut names: Vec<String> = vec![String::from("base")];
+
+    for scenario in scenarios() {
+        // Scenarios that only exclude roads are identical to the base network when there are none to exclude
+        if scenario.unscaled() && excluded(&mut db, iso, &scenario) == 0 {
+            println!("ok - skipping {} scenario, no roads are excluded", &scenario.name);
+            continue;
+        }
+
+        println!("ok - calculating {} scenario", &scenario.name);
+        coverage(pool, iso, buffer, &scenario);
+        names.push(scenario.name);
+    }
+
+    db.execute(format!("
+        CREATE INDEX {iso}_sensitivity_idx
+            ON country_{iso}.{iso}_sensitivity (scenario, id)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    // Rows of each scenario are in the same order, the country followed by each area depth first
+    let results: Vec<Vec<Totals>> = names.iter().map(|name| {
+        totals(&mut db, iso, areas, name)
+    }).collect();
+
+    let mut wtr = csv::Writer::from_path(path).unwrap();
+
+    let mut header: Vec<String> = ["id", "parent", "level", "name", "population", "buffer", "rai", "rai min", "rai max"]
+        .iter().map(|column| column.to_string()).collect();
+    header.extend(names.iter().skip(1).map(|name| format!("rai {}", name)));
+    wtr.write_record(&header).unwrap();
+
+    for (i, base) in results[0].iter().enumerate() {
+        let rais: Vec<Option<f64>> = results.iter().map(|scenario| scenario[i].rai()).collect();
+        wtr.write_record(&record(base, population, buffer, &rais)).unwrap();
+    }
+
+    println!("ok - wrote sensitivity of {} scenarios", names.len() - 1);
+}
+
+///
+/// Return the number of roads of the master network excluded by a scenario
+///
+fn excluded(db: &mut postgres::Client, iso: &String, scenario: &Scenario) -> i64 {
+    match db.query(format!("
+        SELECT
+            count(*)
+        FROM
+            country_{iso}.master
+        WHERE
+            NOT ({filter})
+    ", iso = &iso, filter = scenario.filter).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.get(0).unwrap().get(0)
+    }
+}
+
+///
+/// Calculate the coverage of each pixel under a scenario, a raster tile at a
+/// time as with the coverage of the network itself
+///
+fn coverage(pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, iso: &String, buffer: i64, scenario: &Scenario) {
+    let mut db = pool.get().unwrap();
+
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{iso}.{iso}_scenario
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{iso}.{iso}_scenario AS
+            SELECT
+                ST_Multi(ST_Buffer(geom::GEOGRAPHY, COALESCE(buffer, $1) * $2::FLOAT8)::GEOMETRY) AS geom
+            FROM
+                country_{iso}.master
+            WHERE
+                {filter}
+    ", iso = &iso, filter = scenario.filter).as_str(), &[&buffer, &scenario.scale]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX {iso}_scenario_gix
+            ON country_{iso}.{iso}_scenario USING GIST (geom)
+    ", iso = &iso).as_str(), &[]).unwrap();
+
+    let rids: Vec<i32> = match db.query(format!("
+        SELECT DISTINCT
+            rid
+        FROM
+            country_{iso}.{iso}_geom
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| row.get(0)).collect()
+    };
+
+    overlay(pool, iso, &format!("country_{}.{}_scenario", &iso, &iso), "geom", &rids, "");
+
+    db.execute(format!("
+        INSERT INTO country_{iso}.{iso}_sensitivity (
+            id,
+            scenario,
+            coverage
+        ) SELECT
+            px.id,
+            $1,
+            COALESCE(overlay.coverage, 0)
+        FROM
+            country_{iso}.{iso}_geom AS px
+                LEFT JOIN country_{iso}.{iso}_overlay AS overlay
+                    ON px.id = overlay.id
+    ", iso = &iso).as_str(), &[&scenario.name]).unwrap();
+}
+
+///
+/// Return the totals of the country followed by each area under a given scenario
+///
+fn totals(db: &mut postgres::Client, iso: &String, areas: Option<&Areas>, scenario: &str) -> Vec<Totals> {
+    let mut totals: Vec<Totals> = match db.query(format!("
+        SELECT
+            'country',
+            'all',
+            {totals}
+        FROM
+            country_{iso}.{iso}_geom AS px
+                INNER JOIN country_{iso}.{iso}_sensitivity AS cov
+                    ON px.id = cov.id
+        WHERE
+            cov.scenario = $1
+    ", iso = &iso, totals = TOTALS).as_str(), &[&scenario]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => res.iter().map(|row| Totals::from_row(row)).collect()
+    };
+
+    if let Some(areas) = areas {
+        let leaves: Vec<(usize, Totals)> = match db.query(format!("
+            SELECT
+                '',
+                'all',
+                {totals},
+                px.bounds
+            FROM
+                (
+                    SELECT
+                        frac.bounds,
+                        px.id,
+                        px.urban,
+                        px.pop * frac.frac AS pop
+                    FROM
+                        country_{iso}.{iso}_geom AS px
+                            INNER JOIN country_{iso}.{iso}_bounds_px AS frac
+                                ON px.id = frac.id
+                ) AS px
+                    INNER JOIN country_{iso}.{iso}_sensitivity AS cov
+                        ON px.id = cov.id
+            WHERE
+                cov.scenario = $1
+            GROUP BY
+                px.bounds
+        ", iso = &iso, totals = TOTALS).as_str(), &[&scenario]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.iter().map(|row| {
+                let bounds: i64 = row.get(7);
+                (bounds as usize, Totals::from_row(row))
+            }).collect()
+        };
+
+        totals.extend(areas.rollup(leaves).into_iter().map(|(_, totals)| totals));
+    }
+
+    totals
+}
+
+///
+/// Return a row of the sensitivity report, the RAI of the base network
+/// followed by the range & RAI of each scenario
+///
+fn record(base: &Totals, population: &str, buffer: i64, rais: &Vec<Option<f64>>) -> Vec<String> {
+    let values: Vec<f64> = rais.iter().filter_map(|rai| *rai).collect();
+
+    let min = values.iter().cloned().fold(None, |min: Option<f64>, rai| Some(min.map_or(rai, |min| min.min(rai))));
+    let max = values.iter().cloned().fold(None, |max: Option<f64>, rai| Some(max.map_or(rai, |max| max.max(rai))));
+
+    let format = |rai: Option<f64>| rai.map(|rai| rai.to_string()).unwrap_or_default();
+
+    let mut record = vec![
+        base.id.clone(),
+        base.parent.clone(),
+        base.level.to_string(),
+        base.name.clone(),
+        population.to_string(),
+        buffer.to_string(),
+        format(rais[0]),
+        format(min),
+        format(max)
+    ];
+    record.extend(rais.iter().skip(1).map(|rai| format(*rai)));
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenarios() {
+        let names: Vec<String> = scenarios().into_iter().map(|scenario| scenario.name).collect();
+
+        assert_eq!(names, vec![
+            "buffer -25%",
+            "buffer +25%",
+            "tagged surfaces only",
+            "without added roads"
+        ]);
+    }
+
+    #[test]
+    fn test_unscaled() {
+        let unscaled: Vec<bool> = scenarios().iter().map(|scenario| scenario.unscaled()).collect();
+        assert_eq!(unscaled, vec![false, false, true, true]);
+
+        assert!(Scenario {
+            name: String::from("rounded"),
+            scale: 0.1 + 0.7 + 0.2,
+            filter: "TRUE"
+        }.unscaled());
+    }
+
+    #[test]
+    fn test_record() {
+        let base = Totals::new("Central", "", 1, "Central", "all");
+
+        assert_eq!(record(&base, "gpw_v4_2020", 2000, &vec![Some(50.0), Some(40.0), Some(62.5), None]), vec![
+            "Central", "", "1", "Central", "gpw_v4_2020", "2000", "50", "40", "62.5", "40", "62.5", ""
+        ]);
+
+        assert_eq!(record(&base, "gpw_v4_2020", 2000, &vec![None]), vec![
+            "Central", "", "1", "Central", "gpw_v4_2020", "2000", "", "", ""
+        ]);
+    }
+}
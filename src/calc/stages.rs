@@ -0,0 +1,212 @@
+use std::io::Read;
+use sha2::{Sha256, Digest};
+use postgres::Client;
+
+///
+/// Completion state of a single stage of a calc run
+///
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    /// The stage has not been run, or was run with different inputs
+    Incomplete,
+
+    /// The stage was completed with the same inputs, but the network has since changed
+    Stale,
+
+    /// The stage was completed with the same inputs
+    Complete
+}
+
+///
+/// Records the stages of a calc run that have completed within the country
+/// schema, along with a fingerprint of the inputs each stage was run with,
+/// allowing an interrupted or repeated run to resume from the last complete stage
+///
+pub struct Stages {
+    name: String
+}
+
+impl Stages {
+    pub fn new(iso: &str) -> Self {
+        Stages {
+            name: format!("country_{}.stages", iso)
+        }
+    }
+
+    pub fn create(&self, db: &mut Client) {
+        db.execute(format!("
+            CREATE TABLE IF NOT EXISTS {} (
+                stage       TEXT PRIMARY KEY,
+                key         TEXT NOT NULL,
+                stale       BOOLEAN NOT NULL DEFAULT FALSE,
+                completed   TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        ", &self.name).as_str(), &[]).unwrap();
+    }
+
+    pub fn status(&self, db: &mut Client, stage: &str, key: &str) -> Status {
+        match db.query(format!("
+            SELECT
+                key,
+                stale
+            FROM
+                {}
+            WHERE
+                stage = $1
+        ", &self.name).as_str(), &[&stage]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => match res.get(0) {
+                None => Status::Incomplete,
+                Some(row) => {
+                    let existing: String = row.get(0);
+                    let stale: bool = row.get(1);
+
+                    if existing != key {
+                        Status::Incomplete
+                    } else if stale {
+                        Status::Stale
+                    } else {
+                        Status::Complete
+                    }
+                }
+            }
+        }
+    }
+
+    ///
+    /// Return whether the stage has been completed, regardless of its inputs
+    ///
+    pub fn exists(&self, db: &mut Client, stage: &str) -> bool {
+        match db.query(format!("
+            SELECT
+                count(*)
+            FROM
+                {}
+            WHERE
+                stage = $1
+        ", &self.name).as_str(), &[&stage]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => {
+                let count: i64 = res.get(0).unwrap().get(0);
+                count > 0
+            }
+        }
+    }
+
+    ///
+    /// Return when the stage was last completed, if ever
+    ///
+    pub fn completed(&self, db: &mut Client, stage: &str) -> Option<String> {
+        match db.query(format!("
+            SELECT
+                completed::TEXT
+            FROM
+                {}
+            WHERE
+                stage = $1
+        ", &self.name).as_str(), &[&stage]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.get(0).map(|row| row.get(0))
+        }
+    }
+
+    pub fn complete(&self, db: &mut Client, stage: &str, key: &str) {
+        db.execute(format!("
+            INSERT INTO {} (
+                stage,
+                key
+            ) VALUES (
+                $1,
+                $2
+            ) ON CONFLICT (stage)
+                DO UPDATE SET
+                    key = EXCLUDED.key,
+                    stale = FALSE,
+                    completed = NOW()
+        ", &self.name).as_str(), &[&stage, &key]).unwrap();
+    }
+
+    ///
+    /// Mark all stages starting with the given prefix as stale
+    ///
+    pub fn stale(&self, db: &mut Client, prefix: &str) {
+        db.execute(format!("
+            UPDATE {}
+                SET stale = TRUE
+                WHERE left(stage, length($1)) = $1
+        ", &self.name).as_str(), &[&prefix]).unwrap();
+    }
+
+    ///
+    /// Forget all stages starting with the given prefix, so they are run in full
+    ///
+    pub fn reset(&self, db: &mut Client, prefix: &str) {
+        db.execute(format!("
+            DELETE FROM {}
+                WHERE left(stage, length($1)) = $1
+        ", &self.name).as_str(), &[&prefix]).unwrap();
+    }
+}
+
+///
+/// Return the SHA-256 fingerprint of the given input values, which is stable
+/// across toolchains as it is persisted as the key of each stage
+///
+pub fn fingerprint(values: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+
+    for value in values {
+        hasher.input(value.as_bytes());
+        hasher.input(&[0]);
+    }
+
+    format!("{:x}", hasher.result())
+}
+
+///
+/// Return the SHA-256 checksum of the contents of a file, as recorded with each
+/// run so that the inputs of a run can be verified with standard tools
+///
+pub fn file_fingerprint(path: &str) -> String {
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => panic!("Unable to open {}: {}", path, err)
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 65536];
+
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => hasher.input(&buf[..len]),
+            Err(err) => panic!("Unable to read {}: {}", path, err)
+        };
+    }
+
+    format!("{:x}", hasher.result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(&["gpw_v4_2020", "2000"]), fingerprint(&["gpw_v4_2020", "2000"]));
+        assert_ne!(fingerprint(&["gpw_v4_2020", "2000"]), fingerprint(&["gpw_v4_2015", "2000"]));
+        assert_ne!(fingerprint(&["ab", "c"]), fingerprint(&["a", "bc"]));
+        assert_eq!(fingerprint(&[]), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_ne!(fingerprint(&["abc"]), fingerprint(&["abc", ""]));
+    }
+
+    #[test]
+    fn test_file_fingerprint() {
+        let path = std::env::temp_dir().join("rai_toolkit_test_file_fingerprint");
+        std::fs::write(&path, "abc").unwrap();
+
+        assert_eq!(file_fingerprint(path.to_str().unwrap()), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
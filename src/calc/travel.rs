@@ -0,0 +1,453 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::convert::TryInto;
+use postgres::Client;
+use crate::grid::{LAT_METRES, LNG_METRES};
+
+///
+/// A friction surface aligned to the population raster grid
+///
+/// Friction is a unitless multiplier of distance, a value of 1.0 being unimpeded
+/// walking over flat terrain. Cells without a friction value are impassable.
+///
+#[derive(Debug, PartialEq)]
+pub struct Surface {
+    /// X coordinate of the upper left corner of the grid
+    pub origin_x: f64,
+
+    /// Y coordinate of the upper left corner of the grid
+    pub origin_y: f64,
+
+    /// Width of a cell in degrees
+    pub scale_x: f64,
+
+    /// Height of a cell in degrees (negative for north-up rasters)
+    pub scale_y: f64,
+
+    pub cols: usize,
+    pub rows: usize,
+
+    friction: Vec<Option<f64>>
+}
+
+#[derive(Debug, PartialEq)]
+struct State {
+    cost: f64,
+    cell: usize
+}
+
+impl Eq for State {}
+
+impl Ord for State {
+    // Reversed so the BinaryHeap returns the lowest cost first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Surface {
+    pub fn new(origin_x: f64, origin_y: f64, scale_x: f64, scale_y: f64, cols: usize, rows: usize) -> Self {
+        Surface {
+            origin_x,
+            origin_y,
+            scale_x,
+            scale_y,
+            cols,
+            rows,
+            friction: vec![None; cols * rows]
+        }
+    }
+
+    ///
+    /// Return the index of the cell containing the given coordinate
+    ///
+    pub fn cell(&self, x: f64, y: f64) -> Option<usize> {
+        let col = ((x - self.origin_x) / self.scale_x).floor();
+        let row = ((y - self.origin_y) / self.scale_y).floor();
+
+        if col < 0.0 || row < 0.0 || col >= self.cols as f64 || row >= self.rows as f64 {
+            None
+        } else {
+            Some(row as usize * self.cols + col as usize)
+        }
+    }
+
+    pub fn set(&mut self, cell: usize, friction: Option<f64>) {
+        self.friction[cell] = friction;
+    }
+
+    ///
+    /// Return the cells that a line passes through, sampling the line
+    /// at a quarter of the cell size
+    ///
+    pub fn rasterize(&self, line: &geo::LineString<f64>) -> Vec<usize> {
+        let step = self.scale_x.abs().min(self.scale_y.abs()) / 4.0;
+
+        let mut cells: Vec<usize> = Vec::new();
+        for segment in line.lines() {
+            let dx = segment.end.x - segment.start.x;
+            let dy = segment.end.y - segment.start.y;
+            let steps = ((dx * dx + dy * dy).sqrt() / step).ceil().max(1.0) as usize;
+
+            for i in 0..=steps {
+                let frac = i as f64 / steps as f64;
+
+                match self.cell(segment.start.x + dx * frac, segment.start.y + dy * frac) {
+                    Some(cell) => {
+                        if cells.last() != Some(&cell) {
+                            cells.push(cell);
+                        }
+                    },
+                    None => ()
+                };
+            }
+        }
+
+        cells.sort();
+        cells.dedup();
+        cells
+    }
+
+    ///
+    /// Calculate the cost (effective walking metres) from every cell to
+    /// the closest source cell, moving between the 8 neighbours of a cell
+    ///
+    /// Unreachable cells have a cost of infinity
+    ///
+    pub fn cost(&self, sources: &Vec<usize>) -> Vec<f64> {
+        let mut costs = vec![std::f64::INFINITY; self.cols * self.rows];
+        let mut heap = BinaryHeap::new();
+
+        for source in sources {
+            costs[*source] = 0.0;
+            heap.push(State { cost: 0.0, cell: *source });
+        }
+
+        while let Some(State { cost, cell }) = heap.pop() {
+            if cost > costs[cell] {
+                continue;
+            }
+
+            let row = cell / self.cols;
+            let col = cell % self.cols;
+
+            // Source cells (roads) are always passable, even if the underlying
+            // terrain is not, ie: a bridge over a river
+            let friction = self.friction[cell].unwrap_or(1.0);
+
+            let lat = self.origin_y + self.scale_y * (row as f64 + 0.5);
+            let dx = self.scale_x.abs() * LNG_METRES * lat.to_radians().cos();
+            let dy = self.scale_y.abs() * LAT_METRES;
+
+            for (drow, dcol) in &[(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)] {
+                let nrow = row as i64 + drow;
+                let ncol = col as i64 + dcol;
+
+                if nrow < 0 || ncol < 0 || nrow >= self.rows as i64 || ncol >= self.cols as i64 {
+                    continue;
+                }
+
+                let neighbour = nrow as usize * self.cols + ncol as usize;
+
+                let neighbour_friction = match self.friction[neighbour] {
+                    Some(friction) => friction,
+                    None => continue
+                };
+
+                let distance = ((*dcol as f64 * dx).powi(2) + (*drow as f64 * dy).powi(2)).sqrt();
+                let next = cost + distance * (friction + neighbour_friction) / 2.0;
+
+                if next < costs[neighbour] {
+                    costs[neighbour] = next;
+                    heap.push(State { cost: next, cell: neighbour });
+                }
+            }
+        }
+
+        costs
+    }
+}
+
+///
+/// Parse the name of a friction raster table, given in the form [SCHEMA.]TABLE, as it is
+/// interpolated into the query sampling it
+///
+pub fn table(input: &str) -> Result<String, String> {
+    if input.is_empty() || !input.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.') {
+        return Err(format!("--friction {} must be a raster table in the form [schema.]table", input));
+    }
+
+    Ok(input.to_string())
+}
+
+///
+/// Calculate the travel cost from each population pixel to the closest road in the
+/// master network, storing the result in the travel column of {iso}_geom
+///
+/// The grid is built from every pixel of the {iso}_raster clip, including unpopulated
+/// pixels, so that routes can pass through them. If a friction raster table is given, it
+/// is sampled at the centre of each pixel, otherwise all terrain is considered unimpeded
+///
+/// Pixels without a friction value, being NODATA or outside of the friction raster, are
+/// impassable, the number of which is reported
+///
+pub fn travel(db: &mut Client, iso: &String, friction: Option<&str>) {
+    let (origin_x, origin_y, scale_x, scale_y, max_x, min_y): (f64, f64, f64, f64, f64, f64) = match db.query(format!("
+        SELECT
+            MIN(ST_UpperLeftX(rast)),
+            MAX(ST_UpperLeftY(rast)),
+            MAX(ST_ScaleX(rast)),
+            MIN(ST_ScaleY(rast)),
+            MAX(ST_UpperLeftX(rast) + ST_Width(rast) * ST_ScaleX(rast)),
+            MIN(ST_UpperLeftY(rast) + ST_Height(rast) * ST_ScaleY(rast))
+        FROM
+            country_{iso}.{iso}_raster
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => {
+            let row = res.get(0).unwrap();
+            (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5))
+        }
+    };
+
+    let cols = ((max_x - origin_x) / scale_x).round() as usize;
+    let rows = ((min_y - origin_y) / scale_y).round() as usize;
+
+    let mut surface = Surface::new(origin_x, origin_y, scale_x, scale_y, cols, rows);
+
+    // The friction raster is clipped to the extent of the population grid once, so that
+    // each pixel is sampled by a single join rather than a search of the whole raster
+    let sample = match friction {
+        None => String::from("
+            SELECT
+                ST_X(px.geom),
+                ST_Y(px.geom),
+                1.0::FLOAT8
+            FROM
+                px
+        "),
+        Some(friction) => {
+            db.execute(format!("
+                DROP TABLE IF EXISTS country_{iso}.{iso}_friction
+            ", iso = &iso).as_str(), &[]).unwrap();
+
+            db.execute(format!("
+                CREATE TABLE country_{iso}.{iso}_friction AS
+                    SELECT
+                        ST_Clip(friction.rast, extent.geom) AS rast
+                    FROM
+                        {friction} AS friction,
+                        (
+                            SELECT
+                                ST_Envelope(ST_Collect(ST_Envelope(rast))) AS geom
+                            FROM
+                                country_{iso}.{iso}_raster
+                        ) AS extent
+                    WHERE
+                        ST_Intersects(friction.rast, extent.geom)
+            ", iso = &iso, friction = friction).as_str(), &[]).unwrap();
+
+            db.execute(format!("
+                CREATE INDEX {iso}_friction_gix
+                    ON country_{iso}.{iso}_friction USING GIST (ST_ConvexHull(rast))
+            ", iso = &iso).as_str(), &[]).unwrap();
+
+            format!("
+                SELECT
+                    ST_X(px.geom),
+                    ST_Y(px.geom),
+                    ST_Value(friction.rast, px.geom)
+                FROM
+                    px
+                        INNER JOIN country_{iso}.{iso}_friction AS friction
+                            ON ST_Intersects(friction.rast, px.geom)
+            ", iso = &iso)
+        }
+    };
+
+    match db.query(format!("
+        WITH px AS (
+            SELECT
+                (ST_PixelAsCentroids(rast)).geom AS geom
+            FROM
+                country_{iso}.{iso}_raster
+        )
+        {sample}
+    ", iso = &iso, sample = sample).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(rows) => {
+            for row in rows.iter() {
+                // Where friction tiles overlap a pixel may be sampled more than once, only
+                // values are kept so that NODATA of one tile does not mask another
+                let value: Option<f64> = row.get(2);
+
+                match (surface.cell(row.get(0), row.get(1)), value) {
+                    (Some(cell), Some(value)) => surface.set(cell, Some(value)),
+                    _ => ()
+                };
+            }
+        }
+    };
+
+    let impassable = surface.friction.iter().filter(|friction| friction.is_none()).count();
+    if friction.is_some() && impassable > 0 {
+        println!("ok - {} cells without a friction value (NODATA) are impassable", impassable);
+    }
+    println!("ok - built {}x{} friction surface", cols, rows);
+
+    let mut sources: Vec<usize> = Vec::new();
+    match db.query(format!("
+        SELECT
+            ST_AsGeoJSON(geom)
+        FROM
+            country_{iso}.master
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(rows) => {
+            for row in rows.iter() {
+                let geom: String = row.get(0);
+                let geom = match geom.parse::<geojson::GeoJson>() {
+                    Ok(geojson::GeoJson::Geometry(geom)) => geom,
+                    _ => panic!("Invalid network geometry: {}", geom)
+                };
+
+                let geom: geo::MultiLineString<f64> = match geom.value.try_into() {
+                    Ok(geom) => geom,
+                    Err(err) => panic!("Invalid network geometry: {}", err)
+                };
+
+                for line in geom.0.iter() {
+                    sources.append(&mut surface.rasterize(line));
+                }
+            }
+        }
+    };
+
+    sources.sort();
+    sources.dedup();
+
+    let costs = surface.cost(&sources);
+    println!("ok - calculated travel cost from {} road cells", sources.len());
+
+    let mut ids: Vec<i64> = Vec::new();
+    let mut travel: Vec<Option<f64>> = Vec::new();
+
+    match db.query(format!("
+        SELECT
+            id,
+            ST_X(ST_Centroid(geom)),
+            ST_Y(ST_Centroid(geom))
+        FROM
+            country_{iso}.{iso}_geom
+    ", iso = &iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(rows) => {
+            for row in rows.iter() {
+                let cost = match surface.cell(row.get(1), row.get(2)) {
+                    Some(cell) if costs[cell].is_finite() => Some(costs[cell]),
+                    _ => None
+                };
+
+                ids.push(row.get(0));
+                travel.push(cost);
+            }
+        }
+    };
+
+    db.execute(format!("
+        UPDATE country_{iso}.{iso}_geom AS px
+            SET travel = costs.travel
+            FROM (
+                SELECT
+                    UNNEST($1::BIGINT[]) AS id,
+                    UNNEST($2::FLOAT8[]) AS travel
+            ) costs
+            WHERE
+                px.id = costs.id
+    ", iso = &iso).as_str(), &[&ids, &travel]).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        assert_eq!(table("friction.walking"), Ok(String::from("friction.walking")));
+        assert_eq!(table("walking_2019"), Ok(String::from("walking_2019")));
+
+        assert!(table("").is_err());
+        assert!(table("Friction.walking").is_err());
+        assert!(table("friction.walking; DROP TABLE master").is_err());
+    }
+
+    fn surface() -> Surface {
+        let mut surface = Surface::new(0.0, 0.03, 0.01, -0.01, 3, 3);
+
+        for cell in 0..9 {
+            surface.set(cell, Some(1.0));
+        }
+
+        surface
+    }
+
+    #[test]
+    fn test_cell() {
+        let surface = surface();
+
+        assert_eq!(surface.cell(0.005, 0.025), Some(0));
+        assert_eq!(surface.cell(0.025, 0.025), Some(2));
+        assert_eq!(surface.cell(0.025, 0.005), Some(8));
+        assert_eq!(surface.cell(-0.005, 0.025), None);
+        assert_eq!(surface.cell(0.005, 0.035), None);
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let surface = surface();
+
+        let line: geo::LineString<f64> = vec![(0.005, 0.025), (0.025, 0.025)].into();
+        assert_eq!(surface.rasterize(&line), vec![0, 1, 2]);
+
+        let line: geo::LineString<f64> = vec![(0.015, 0.025), (0.015, 0.005)].into();
+        assert_eq!(surface.rasterize(&line), vec![1, 4, 7]);
+
+        // Portions of a line outside of the grid are ignored
+        let line: geo::LineString<f64> = vec![(-0.5, 0.015), (0.005, 0.015)].into();
+        assert_eq!(surface.rasterize(&line), vec![3]);
+    }
+
+    #[test]
+    fn test_cost() {
+        let mut surface = surface();
+
+        let costs = surface.cost(&vec![0]);
+        assert_eq!(costs[0], 0.0);
+        assert!((costs[1] - 1113.2).abs() < 1.0);
+        assert!((costs[3] - 1105.74).abs() < 1.0);
+        assert!(costs[4] < costs[1] + costs[3]);
+        assert!((costs[2] - 2.0 * costs[1]).abs() < 1.0);
+
+        // Entering a cell with a friction of 3 from unimpeded terrain doubles the cost
+        surface.set(1, Some(3.0));
+        let costs = surface.cost(&vec![0]);
+        assert!((costs[1] - 2226.4).abs() < 1.0);
+
+        // Impassable cells must be routed around
+        let mut surface = Surface::new(0.0, 0.03, 0.01, -0.01, 3, 3);
+        for cell in &[0, 2, 3, 5, 6, 7, 8] {
+            surface.set(*cell, Some(1.0));
+        }
+
+        let costs = surface.cost(&vec![0]);
+        assert_eq!(costs[1], std::f64::INFINITY);
+        assert_eq!(costs[4], std::f64::INFINITY);
+        assert!(costs[2] > 4.0 * 1100.0);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::pg;
+
+///
+/// What was done with a road of the new network
+///
+#[derive(Debug, PartialEq, Clone)]
+pub enum Action {
+    /// The whole road was added to the master network
+    Added,
+
+    /// Only the portions of the road outside of the master network were added
+    Split,
+
+    /// The road was matched to a master road, & its attributes merged onto it
+    Conflated,
+
+    /// The road is already mapped by the master network, but no master road matched it
+    Dropped
+}
+
+impl Action {
+    ///
+    /// Return the action taken for a road, given whether any portion of it was missing from,
+    /// or retained by, the master network & whether the retained portion matched a master road
+    ///
+    pub fn new(missing: bool, retained: bool, matched: bool) -> Self {
+        if !retained {
+            Action::Added
+        } else if missing {
+            Action::Split
+        } else if matched {
+            Action::Conflated
+        } else {
+            Action::Dropped
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Added => "added",
+            Action::Split => "split",
+            Action::Conflated => "conflated",
+            Action::Dropped => "dropped"
+        }
+    }
+}
+
+///
+/// How a road of the new network was matched to a master road
+///
+#[derive(Debug, PartialEq, Clone)]
+pub enum Method {
+    Name,
+    Geometry
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Name => "name",
+            Method::Geometry => "geometry"
+        }
+    }
+}
+
+///
+/// The conflation decision made for a single road of the new network
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Decision {
+    /// id of the road in the new network
+    pub id: i64,
+
+    pub action: Action,
+
+    /// id of the matched master road
+    pub master: Option<i64>,
+
+    pub method: Option<Method>,
+
+    /// Score (0-100) of the name match, from the linker
+    pub score: Option<f64>,
+
+    /// Fraction of the length of the road within the buffer of the master network
+    pub coverage: Option<f64>,
+
+    /// Distance (metres) to the matched master road, or the nearest master road if unmatched
+    pub distance: Option<f64>
+}
+
+impl Decision {
+    pub fn new(id: i64, action: Action) -> Self {
+        Decision {
+            id: id,
+            action: action,
+            master: None,
+            method: None,
+            score: None,
+            coverage: None,
+            distance: None
+        }
+    }
+
+    ///
+    /// Record the decision in the decisions table
+    ///
+    pub fn record(&self, db: &mut postgres::Client) {
+        db.execute("
+            INSERT INTO decisions (
+                id,
+                action,
+                master,
+                method,
+                score,
+                coverage,
+                distance
+            ) VALUES (
+                $1,
+                $2,
+                $3,
+                $4,
+                $5,
+                $6,
+                $7
+            )
+        ", &[
+            &self.id,
+            &self.action.as_str(),
+            &self.master,
+            &self.method.as_ref().map(|method| method.as_str()),
+            &self.score,
+            &self.coverage,
+            &self.distance
+        ]).unwrap();
+    }
+}
+
+///
+/// Create the decisions table, recording the decision made for each road of the new network
+///
+pub fn create(db: &mut postgres::Client) {
+    db.execute("
+        DROP TABLE IF EXISTS decisions
+    ", &[]).unwrap();
+
+    db.execute("
+        CREATE UNLOGGED TABLE decisions (
+            id BIGINT,
+            action TEXT,
+            master BIGINT,
+            method TEXT,
+            score DOUBLE PRECISION,
+            coverage DOUBLE PRECISION,
+            distance DOUBLE PRECISION
+        )
+    ", &[]).unwrap();
+}
+
+///
+/// Write the decision made for each road of the new network as line-delimited GeoJSON,
+/// along with the geometry, name & source of the new road
+///
+pub fn write(pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, path: &str) {
+    let mut output = std::fs::File::create(path).unwrap();
+
+    let mut stream = pg::stream::PGStream::new(pool.get().unwrap(), String::from("next"), String::from("
+        DECLARE next CURSOR FOR
+            SELECT
+                json_build_object(
+                    'type', 'Feature',
+                    'properties', json_build_object(
+                        'id', decisions.id,
+                        'source', new.source,
+                        'name', new.props->>'name',
+                        'action', decisions.action,
+                        'master', decisions.master,
+                        'method', decisions.method,
+                        'score', decisions.score,
+                        'coverage', decisions.coverage,
+                        'distance', decisions.distance
+                    ),
+                    'geometry', ST_AsGeoJSON(new.geom)::JSON
+                )::TEXT
+            FROM
+                decisions
+                    INNER JOIN new
+                        ON decisions.id = new.id
+            ORDER BY
+                decisions.id
+    "), &[]).unwrap();
+
+    std::io::copy(&mut stream, &mut output).unwrap();
+
+    println!("ok - wrote conflation decisions");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action() {
+        assert_eq!(Action::new(false, false, false), Action::Added);
+        assert_eq!(Action::new(true, false, false), Action::Added);
+        assert_eq!(Action::new(true, true, false), Action::Split);
+        assert_eq!(Action::new(true, true, true), Action::Split);
+        assert_eq!(Action::new(false, true, true), Action::Conflated);
+        assert_eq!(Action::new(false, true, false), Action::Dropped);
+
+        assert_eq!(Action::Dropped.as_str(), "dropped");
+    }
+}
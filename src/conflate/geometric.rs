@@ -0,0 +1,393 @@
+use crate::grid::{LAT_METRES, LNG_METRES};
+
+/// Minimum fraction of a new road within the buffer of a master road for them to be the same road
+pub const OVERLAP: f64 = 0.75;
+
+/// Minimum mean absolute cosine of the angle between the overlapping portions of both roads
+pub const HEADING: f64 = 0.9;
+
+/// Maximum distance of any point of the new road from the master road, as a multiple of the buffer
+pub const HAUSDORFF: f64 = 2.0;
+
+/// Maximum discrete Fréchet distance between the new road & the portion of the master road alongside it,
+/// as a multiple of the buffer
+pub const FRECHET: f64 = 2.0;
+
+///
+/// Geometric similarity of a new road to a master road
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Similarity {
+    /// Directed Hausdorff distance (metres), the furthest any point of the new road is from the master road
+    pub hausdorff: f64,
+
+    /// Discrete Fréchet distance (metres) between the new road & the portion of the master road
+    /// between the nearest points to either end of the new road, so that unlike the Hausdorff
+    /// distance the order of the points along both roads is compared
+    pub frechet: f64,
+
+    /// Mean absolute cosine of the angle between both roads, where the new road is within the buffer
+    pub heading: f64,
+
+    /// Fraction of the length of the new road within the buffer of the master road
+    pub overlap: f64
+}
+
+impl Similarity {
+    ///
+    /// Whether both roads are likely to be the same road, mapped by both sources
+    ///
+    pub fn is_match(&self, buffer: f64) -> bool {
+        self.overlap >= OVERLAP
+            && self.heading >= HEADING
+            && self.hausdorff <= buffer * HAUSDORFF
+            && self.frechet <= buffer * FRECHET
+    }
+}
+
+///
+/// Return the id & similarity of the candidate master road most similar to a new road,
+/// if any are likely to be the same road
+///
+pub fn best(new: &geo::MultiLineString<f64>, candidates: &Vec<(i64, geo::MultiLineString<f64>)>, buffer: f64) -> Option<(i64, Similarity)> {
+    candidates.iter().map(|(id, master)| {
+        (*id, similarity(new, master, buffer))
+    }).filter(|(_, similarity)| {
+        similarity.is_match(buffer)
+    }).fold(None, |best: Option<(i64, Similarity)>, (id, similarity)| match best {
+        Some(ref current) if current.1.overlap > similarity.overlap => best,
+        Some(ref current) if current.1.overlap == similarity.overlap && current.1.hausdorff <= similarity.hausdorff => best,
+        _ => Some((id, similarity))
+    })
+}
+
+///
+/// Compare a new road against a master road, by sampling points along the new road
+/// at intervals of half the buffer (metres)
+///
+pub fn similarity(new: &geo::MultiLineString<f64>, master: &geo::MultiLineString<f64>, buffer: f64) -> Similarity {
+    let origin = match new.0.iter().flat_map(|ln| ln.0.iter()).next() {
+        Some(origin) => *origin,
+        None => {
+            return Similarity {
+                hausdorff: std::f64::INFINITY,
+                frechet: std::f64::INFINITY,
+                heading: 0.0,
+                overlap: 0.0
+            };
+        }
+    };
+
+    let project = |coord: &geo::Coordinate<f64>| -> (f64, f64) {
+        (
+            (coord.x - origin.x) * LNG_METRES * origin.y.to_radians().cos(),
+            (coord.y - origin.y) * LAT_METRES
+        )
+    };
+
+    let segments = |lines: &geo::MultiLineString<f64>| -> Vec<((f64, f64), (f64, f64))> {
+        lines.0.iter().flat_map(|ln| ln.0.windows(2).map(|pair| (project(&pair[0]), project(&pair[1]))).collect::<Vec<_>>()).collect()
+    };
+
+    let new_segments = segments(new);
+    let master_segments = segments(master);
+
+    let step = (buffer / 2.0).max(1.0);
+
+    let mut samples = 0;
+    let mut within = 0;
+    let mut hausdorff: f64 = 0.0;
+    let mut heading = 0.0;
+
+    for (start, end) in new_segments.iter() {
+        let length = distance(*start, *end);
+        if length == 0.0 {
+            continue;
+        }
+
+        let direction = ((end.0 - start.0) / length, (end.1 - start.1) / length);
+        let count = (length / step).ceil() as usize;
+
+        for i in 0..=count {
+            let t = i as f64 / count as f64;
+            let point = (start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t);
+
+            let nearest = master_segments.iter().map(|segment| {
+                (nearest(point, *segment), segment)
+            }).min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            samples += 1;
+
+            match nearest {
+                None => {
+                    hausdorff = std::f64::INFINITY;
+                },
+                Some((dist, (mstart, mend))) => {
+                    hausdorff = hausdorff.max(dist);
+
+                    if dist <= buffer {
+                        within += 1;
+
+                        let mlength = distance(*mstart, *mend);
+                        if mlength > 0.0 {
+                            let mdirection = ((mend.0 - mstart.0) / mlength, (mend.1 - mstart.1) / mlength);
+                            heading += (direction.0 * mdirection.0 + direction.1 * mdirection.1).abs();
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    // Each part of the new road is compared with the master part it is closest to following
+    let polylines = |lines: &geo::MultiLineString<f64>| -> Vec<Vec<(f64, f64)>> {
+        lines.0.iter().map(|ln| ln.0.iter().map(|coord| project(coord)).collect()).collect()
+    };
+
+    let master_lines = polylines(master);
+    let frechet = polylines(new).iter().filter(|line| !line.is_empty()).map(|line| {
+        master_lines.iter().filter(|master| !master.is_empty()).map(|master| {
+            let from = locate(line[0], master);
+            let to = locate(line[line.len() - 1], master);
+
+            frechet(&densify(line, step), &densify(&substring(master, from, to), step))
+        }).fold(std::f64::INFINITY, f64::min)
+    }).fold(0.0, f64::max);
+
+    Similarity {
+        hausdorff: if samples == 0 { std::f64::INFINITY } else { hausdorff },
+        frechet: if samples == 0 { std::f64::INFINITY } else { frechet },
+        heading: if within == 0 { 0.0 } else { heading / within as f64 },
+        overlap: if samples == 0 { 0.0 } else { within as f64 / samples as f64 }
+    }
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+///
+/// Return the discrete Fréchet distance between two polylines, the shortest leash allowing
+/// both to be walked from start to end without either walking backwards
+///
+pub fn frechet(a: &Vec<(f64, f64)>, b: &Vec<(f64, f64)>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return std::f64::INFINITY;
+    }
+
+    // Only the previous row of the coupling table is kept
+    let mut previous: Vec<f64> = Vec::with_capacity(b.len());
+    for (j, point) in b.iter().enumerate() {
+        let dist = distance(a[0], *point);
+        previous.push(if j == 0 { dist } else { previous[j - 1].max(dist) });
+    }
+
+    for point in a.iter().skip(1) {
+        let mut current: Vec<f64> = Vec::with_capacity(b.len());
+
+        for (j, other) in b.iter().enumerate() {
+            let reach = if j == 0 {
+                previous[0]
+            } else {
+                previous[j].min(previous[j - 1]).min(current[j - 1])
+            };
+
+            current.push(reach.max(distance(*point, *other)));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len() - 1]
+}
+
+///
+/// Return the distance along a polyline of the nearest point of the polyline to a point
+///
+fn locate(point: (f64, f64), line: &Vec<(f64, f64)>) -> f64 {
+    let mut along = 0.0;
+    let mut best = (std::f64::INFINITY, 0.0);
+
+    for pair in line.windows(2) {
+        let length = distance(pair[0], pair[1]);
+
+        let t = if length == 0.0 {
+            0.0
+        } else {
+            (((point.0 - pair[0].0) * (pair[1].0 - pair[0].0) + (point.1 - pair[0].1) * (pair[1].1 - pair[0].1)) / length.powi(2)).max(0.0).min(1.0)
+        };
+
+        let dist = distance(point, (pair[0].0 + (pair[1].0 - pair[0].0) * t, pair[0].1 + (pair[1].1 - pair[0].1) * t));
+        if dist < best.0 {
+            best = (dist, along + length * t);
+        }
+
+        along += length;
+    }
+
+    best.1
+}
+
+///
+/// Return the point at a distance along a polyline
+///
+fn interpolate(line: &Vec<(f64, f64)>, at: f64) -> (f64, f64) {
+    let mut along = 0.0;
+
+    for pair in line.windows(2) {
+        let length = distance(pair[0], pair[1]);
+
+        if length > 0.0 && along + length >= at {
+            let t = ((at - along) / length).max(0.0);
+            return (pair[0].0 + (pair[1].0 - pair[0].0) * t, pair[0].1 + (pair[1].1 - pair[0].1) * t);
+        }
+
+        along += length;
+    }
+
+    line[line.len() - 1]
+}
+
+///
+/// Return the portion of a polyline between two distances along it, running from the
+/// first distance to the second, so reversed if the second is the lesser
+///
+fn substring(line: &Vec<(f64, f64)>, from: f64, to: f64) -> Vec<(f64, f64)> {
+    let (start, end) = (from.min(to), from.max(to));
+
+    let mut portion = vec![interpolate(line, start)];
+    let mut along = 0.0;
+
+    for pair in line.windows(2) {
+        along += distance(pair[0], pair[1]);
+
+        if along > start && along < end {
+            portion.push(pair[1]);
+        }
+    }
+
+    portion.push(interpolate(line, end));
+
+    if from > to {
+        portion.reverse();
+    }
+
+    portion
+}
+
+///
+/// Return the points of a polyline along with points at intervals of at most the given step
+///
+fn densify(line: &Vec<(f64, f64)>, step: f64) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = line.iter().take(1).cloned().collect();
+
+    for pair in line.windows(2) {
+        let count = (distance(pair[0], pair[1]) / step).ceil().max(1.0) as usize;
+
+        for i in 1..=count {
+            let t = i as f64 / count as f64;
+            points.push((pair[0].0 + (pair[1].0 - pair[0].0) * t, pair[0].1 + (pair[1].1 - pair[0].1) * t));
+        }
+    }
+
+    points
+}
+
+///
+/// Return the distance from a point to the nearest point of a segment
+///
+fn nearest(point: (f64, f64), segment: ((f64, f64), (f64, f64))) -> f64 {
+    let (start, end) = segment;
+    let length = (end.0 - start.0).powi(2) + (end.1 - start.1).powi(2);
+
+    if length == 0.0 {
+        return distance(point, start);
+    }
+
+    let t = (((point.0 - start.0) * (end.0 - start.0) + (point.1 - start.1) * (end.1 - start.1)) / length).max(0.0).min(1.0);
+
+    distance(point, (start.0 + (end.0 - start.0) * t, start.1 + (end.1 - start.1) * t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line along the equator, offset north by the given metres
+    fn line(from: f64, to: f64, offset: f64) -> geo::MultiLineString<f64> {
+        geo::MultiLineString(vec![geo::LineString::from(vec![
+            (from / LNG_METRES, offset / LAT_METRES),
+            (to / LNG_METRES, offset / LAT_METRES)
+        ])])
+    }
+
+    #[test]
+    fn test_similarity() {
+        // The same road, mapped 10m apart
+        let same = similarity(&line(0.0, 1000.0, 0.0), &line(0.0, 1000.0, 10.0), 25.0);
+        assert!((same.hausdorff - 10.0).abs() < 0.01);
+        assert!((same.heading - 1.0).abs() < 0.0001);
+        assert_eq!(same.overlap, 1.0);
+        assert!(same.is_match(25.0));
+
+        // A parallel road 100m away
+        let parallel = similarity(&line(0.0, 1000.0, 0.0), &line(0.0, 1000.0, 100.0), 25.0);
+        assert_eq!(parallel.overlap, 0.0);
+        assert!(!parallel.is_match(25.0));
+
+        // A new road that only overlaps the master road for its first half
+        let partial = similarity(&line(0.0, 1000.0, 0.0), &line(0.0, 500.0, 0.0), 25.0);
+        assert!(partial.overlap > 0.5 && partial.overlap < 0.6);
+        assert!(!partial.is_match(25.0));
+
+        // A road crossing the new road at right angles
+        let crossing = geo::MultiLineString(vec![geo::LineString::from(vec![
+            (500.0 / LNG_METRES, -500.0 / LAT_METRES),
+            (500.0 / LNG_METRES, 500.0 / LAT_METRES)
+        ])]);
+        let crossing = similarity(&line(0.0, 1000.0, 0.0), &crossing, 25.0);
+        assert!(crossing.heading < 0.0001);
+        assert!(!crossing.is_match(25.0));
+
+        // A new road doubling back along the master road lies entirely within it, but does not follow it
+        let doubled = geo::MultiLineString(vec![geo::LineString::from(vec![
+            (0.0, 0.0),
+            (1000.0 / LNG_METRES, 0.0),
+            (0.0, 0.0)
+        ])]);
+        let doubled = similarity(&doubled, &line(0.0, 1000.0, 0.0), 25.0);
+        assert!(doubled.hausdorff < 0.01);
+        assert!((doubled.frechet - 1000.0).abs() < 0.1);
+        assert!(!doubled.is_match(25.0));
+
+        // Only the portion of a longer master road alongside the new road is compared, in either direction
+        let reversed = similarity(&line(1000.0, 0.0, 0.0), &line(-500.0, 1500.0, 10.0), 25.0);
+        assert!((reversed.frechet - 10.0).abs() < 0.01);
+        assert!(reversed.is_match(25.0));
+    }
+
+    #[test]
+    fn test_frechet() {
+        assert_eq!(frechet(&vec![(0.0, 0.0), (2.0, 0.0)], &vec![(0.0, 1.0), (2.0, 1.0)]), 1.0);
+        assert_eq!(frechet(&vec![(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)], &vec![(0.0, 1.0), (2.0, 1.0)]), 2f64.sqrt());
+
+        // Walking the same points in the opposite direction
+        assert_eq!(frechet(&vec![(0.0, 0.0), (2.0, 0.0)], &vec![(2.0, 0.0), (0.0, 0.0)]), 2.0);
+
+        assert_eq!(frechet(&vec![], &vec![(0.0, 0.0)]), std::f64::INFINITY);
+    }
+
+    #[test]
+    fn test_best() {
+        let candidates = vec![
+            (1, line(0.0, 1000.0, 100.0)),
+            (2, line(0.0, 1000.0, 15.0)),
+            (3, line(-100.0, 1100.0, 5.0))
+        ];
+
+        let (id, _) = best(&line(0.0, 1000.0, 0.0), &candidates, 25.0).unwrap();
+        assert_eq!(id, 3);
+
+        assert_eq!(best(&line(0.0, 1000.0, 0.0), &vec![(1, line(0.0, 1000.0, 100.0))], 25.0), None);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use serde_json::{Map, Value};
+use crate::filter;
+
+/// Property of a conflated master road recording the source of each of its attributes
+pub const PROVENANCE: &str = "provenance";
+
+/// Property of each output road recording the network it came from
+pub const SOURCE: &str = "rai:source";
+
+/// Properties set by the conflation itself, which are never merged
+const RESERVED: [&str; 5] = ["added", "conflated", "split", PROVENANCE, SOURCE];
+
+///
+/// How the value of an attribute is chosen when a road of the new network is merged onto a master road
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// The master value is kept, the attribute is not added if the master road lacks it
+    KeepMaster,
+
+    /// The new value replaces the master value, where the new road has the attribute
+    PreferNew,
+
+    /// The master value is kept unless it is missing or null
+    PreferNonNull,
+
+    /// Distinct values of both roads are joined with a `;`
+    Concatenate,
+
+    /// The value of the better surface is kept, paved surfaces being preferred to unknown surfaces,
+    /// & unknown surfaces to unpaved surfaces
+    BetterSurface
+}
+
+///
+/// A merge policy controlling how the attributes of matched roads are combined
+///
+/// Policies are read from a JSON file in the form:
+///
+/// {
+///     "default": "prefer_non_null",
+///     "keys": {
+///         "surface": "better_surface",
+///         "name": "prefer_new",
+///         "ref": "concatenate"
+///     }
+/// }
+///
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Merge {
+    /// Policy of any attribute without an explicit policy, falling back to the policy
+    /// of the method by which the roads were matched
+    #[serde(default)]
+    pub default: Option<Policy>,
+
+    /// Policies keyed by the attribute name
+    #[serde(default)]
+    pub keys: HashMap<String, Policy>
+}
+
+impl Merge {
+    pub fn new(merge: &str) -> Result<Self, String> {
+        let merge: Merge = match serde_json::from_str(merge) {
+            Ok(merge) => merge,
+            Err(err) => { return Err(format!("Invalid merge policy: {}", err)); }
+        };
+
+        for key in RESERVED.iter() {
+            if merge.keys.contains_key(*key) {
+                return Err(format!("{} is set by conflation and cannot be given a merge policy", key));
+            }
+        }
+
+        Ok(merge)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(merge) => Merge::new(&merge),
+            Err(err) => Err(format!("Unable to read merge policy file: {}", err))
+        }
+    }
+
+    ///
+    /// Return the policy of a given attribute, falling back to the given policy
+    /// if neither the attribute nor the default have one
+    ///
+    pub fn policy(&self, key: &str, fallback: Policy) -> Policy {
+        match self.keys.get(key) {
+            Some(policy) => *policy,
+            None => self.default.unwrap_or(fallback)
+        }
+    }
+
+    ///
+    /// Merge the attributes of a road from the given source onto those of a master road from the
+    /// origin source, recording the source of each resulting attribute in its provenance property
+    ///
+    /// Sources are ranked by their order in priority, the highest first, with any source not
+    /// given ranking below them all. A fallback policy never replaces an attribute that came
+    /// from a source ranking above the given source, only filling it in if missing
+    ///
+    pub fn merge(&self, master: &Map<String, Value>, new: &Map<String, Value>, origin: &str, source: &str, priority: &[String], fallback: Policy) -> Map<String, Value> {
+        let mut provenance = match master.get(PROVENANCE) {
+            Some(Value::Object(provenance)) => provenance.clone(),
+            _ => Map::new()
+        };
+
+        let mut merged = master.clone();
+
+        let mut keys: Vec<&String> = master.keys().chain(new.keys()).filter(|key| {
+            !RESERVED.contains(&key.as_str())
+        }).collect();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            let previous = provenance.get(key).and_then(|source| source.as_str()).unwrap_or(origin).to_string();
+
+            let fallback = if fallback == Policy::PreferNew && outranks(priority, &previous, source) {
+                Policy::PreferNonNull
+            } else {
+                fallback
+            };
+
+            let (value, from) = choose(
+                self.policy(key, fallback),
+                master.get(key),
+                new.get(key),
+                &previous,
+                source
+            );
+
+            match value {
+                None => {
+                    merged.remove(key);
+                    provenance.remove(key);
+                },
+                Some(value) => {
+                    merged.insert(key.to_string(), value);
+                    provenance.insert(key.to_string(), Value::from(from));
+                }
+            };
+        }
+
+        merged.insert(String::from(PROVENANCE), Value::Object(provenance));
+
+        merged
+    }
+}
+
+///
+/// Whether any of the sources an attribute came from ranks above the given source
+///
+fn outranks(priority: &[String], previous: &str, source: &str) -> bool {
+    let rank = |source: &str| priority.iter().position(|ranked| ranked == source).unwrap_or(priority.len());
+
+    previous.split(';').any(|previous| rank(previous) < rank(source))
+}
+
+///
+/// Choose the value of a single attribute & the source it came from, None if the
+/// attribute is absent from the merged road
+///
+fn choose(policy: Policy, master: Option<&Value>, new: Option<&Value>, previous: &str, source: &str) -> (Option<Value>, String) {
+    let kept = || -> (Option<Value>, String) {
+        match master {
+            None => (None, String::new()),
+            Some(master) => (Some(master.clone()), previous.to_string())
+        }
+    };
+
+    let new = match new {
+        // Attributes the new road lacks are always kept from the master road
+        None => { return kept(); },
+        Some(new) => new
+    };
+
+    let replaced = || -> (Option<Value>, String) {
+        (Some(new.clone()), source.to_string())
+    };
+
+    let missing = master.map(|master| master.is_null()).unwrap_or(true);
+
+    match policy {
+        Policy::KeepMaster => kept(),
+        Policy::PreferNew => replaced(),
+        Policy::PreferNonNull => if missing {
+            replaced()
+        } else {
+            kept()
+        },
+        Policy::Concatenate => match (master.and_then(|master| master.as_str()), new.as_str()) {
+            (Some(master), Some(new)) => {
+                let mut values: Vec<&str> = master.split(';').map(|value| value.trim()).collect();
+                let len = values.len();
+
+                for value in new.split(';').map(|value| value.trim()) {
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+
+                if values.len() == len {
+                    kept()
+                } else {
+                    let from = if previous.split(';').any(|previous| previous == source) {
+                        previous.to_string()
+                    } else {
+                        format!("{};{}", previous, source)
+                    };
+
+                    (Some(Value::from(values.join(";"))), from)
+                }
+            },
+            _ => choose(Policy::PreferNonNull, master, Some(new), previous, source)
+        },
+        Policy::BetterSurface => if surface_rank(new) > surface_rank(master.unwrap_or(&Value::Null)) {
+            replaced()
+        } else {
+            kept()
+        }
+    }
+}
+
+///
+/// Rank a surface value, higher being better
+///
+fn surface_rank(surface: &Value) -> i32 {
+    match surface.as_str() {
+        None => -1,
+        Some(surface) if filter::pref_surface().contains(&surface) => 2,
+        Some(surface) if filter::reject_surface().contains(&surface) => 0,
+        Some(_) => 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(props) => props,
+            _ => panic!("props must be an object")
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        let merge = Merge::new(r#"{
+            "default": "prefer_non_null",
+            "keys": {
+                "surface": "better_surface",
+                "name": "prefer_new"
+            }
+        }"#).unwrap();
+
+        assert_eq!(merge.policy("surface", Policy::PreferNew), Policy::BetterSurface);
+        assert_eq!(merge.policy("highway", Policy::PreferNew), Policy::PreferNonNull);
+        assert_eq!(Merge::new("{}").unwrap().policy("highway", Policy::PreferNew), Policy::PreferNew);
+
+        assert!(Merge::new(r#"{ "keys": { "surface": "best" } }"#).is_err());
+        assert_eq!(Merge::new(r#"{ "keys": { "conflated": "prefer_new" } }"#), Err(String::from("conflated is set by conflation and cannot be given a merge policy")));
+        assert_eq!(Merge::new(r#"{ "keys": { "rai:source": "prefer_new" } }"#), Err(String::from("rai:source is set by conflation and cannot be given a merge policy")));
+    }
+
+    #[test]
+    fn test_merge() {
+        let merge = Merge::new(r#"{
+            "keys": {
+                "surface": "keep_master",
+                "smoothness": "keep_master",
+                "highway": "prefer_non_null",
+                "ref": "concatenate",
+                "tracktype": "better_surface"
+            }
+        }"#).unwrap();
+
+        let master = props(serde_json::json!({
+            "name": "Ruta 1",
+            "surface": "asphalt",
+            "highway": null,
+            "ref": "PY01",
+            "lanes": 2
+        }));
+
+        let new = props(serde_json::json!({
+            "name": "Ruta Mariscal Estigarribia",
+            "surface": "unpaved",
+            "smoothness": "bad",
+            "highway": "primary",
+            "ref": "PY01;R1"
+        }));
+
+        assert_eq!(Value::from(merge.merge(&master, &new, "master", "new", &[], Policy::PreferNew)), serde_json::json!({
+            "name": "Ruta Mariscal Estigarribia",
+            "surface": "asphalt",
+            "highway": "primary",
+            "ref": "PY01;R1",
+            "lanes": 2,
+            "provenance": {
+                "name": "new",
+                "surface": "master",
+                "highway": "new",
+                "ref": "master;new",
+                "lanes": "master"
+            }
+        }));
+
+        // The provenance of attributes merged onto the master road earlier is kept
+        let master = merge.merge(&master, &new, "master", "new", &[], Policy::PreferNew);
+        let merged = merge.merge(&master, &props(serde_json::json!({ "name": "Ruta 1" })), "master", "roads", &[], Policy::PreferNonNull);
+        assert_eq!(merged.get("name"), Some(&Value::from("Ruta Mariscal Estigarribia")));
+        assert_eq!(merged.get(PROVENANCE).unwrap().get("name"), Some(&Value::from("new")));
+    }
+
+    #[test]
+    fn test_priority() {
+        let merge = Merge::new(r#"{ "keys": { "ref": "concatenate" } }"#).unwrap();
+        let priority = vec![String::from("ministry"), String::from("donor")];
+
+        let master = props(serde_json::json!({
+            "name": "Ruta 1",
+            "surface": "asphalt",
+            "ref": "PY01"
+        }));
+
+        // The highest priority source replaces the attributes of the master network
+        let master = merge.merge(&master, &props(serde_json::json!({
+            "name": "Ruta Mariscal Estigarribia",
+            "ref": "R1"
+        })), "osm", "ministry", &priority, Policy::PreferNew);
+
+        // A lower priority source only replaces the attributes still from the master network,
+        // & fills in those that are missing
+        let merged = merge.merge(&master, &props(serde_json::json!({
+            "name": "Ruta Transchaco",
+            "surface": "paved",
+            "lanes": 2,
+            "ref": "R9"
+        })), "osm", "donor", &priority, Policy::PreferNew);
+
+        assert_eq!(Value::from(merged), serde_json::json!({
+            "name": "Ruta Mariscal Estigarribia",
+            "surface": "paved",
+            "lanes": 2,
+            "ref": "PY01;R1;R9",
+            "provenance": {
+                "name": "ministry",
+                "surface": "donor",
+                "lanes": "donor",
+                "ref": "osm;ministry;donor"
+            }
+        }));
+
+        assert!(outranks(&priority, "ministry", "donor"));
+        assert!(outranks(&priority, "osm;ministry", "donor"));
+        assert!(!outranks(&priority, "osm", "ministry"));
+        assert!(!outranks(&priority, "donor", "donor"));
+    }
+
+    #[test]
+    fn test_choose() {
+        let paved = Value::from("asphalt");
+        let unpaved = Value::from("dirt");
+        let unknown = Value::from("unknown");
+
+        assert_eq!(choose(Policy::BetterSurface, Some(&unpaved), Some(&paved), "master", "new"), (Some(paved.clone()), String::from("new")));
+        assert_eq!(choose(Policy::BetterSurface, Some(&paved), Some(&unknown), "master", "new"), (Some(paved.clone()), String::from("master")));
+        assert_eq!(choose(Policy::BetterSurface, None, Some(&unpaved), "master", "new"), (Some(unpaved.clone()), String::from("new")));
+
+        assert_eq!(choose(Policy::Concatenate, Some(&Value::from("A")), Some(&Value::from("A")), "master", "new"), (Some(Value::from("A")), String::from("master")));
+        assert_eq!(choose(Policy::Concatenate, Some(&Value::Null), Some(&Value::from("B")), "master", "new"), (Some(Value::from("B")), String::from("new")));
+
+        assert_eq!(choose(Policy::KeepMaster, None, Some(&paved), "master", "new"), (None, String::new()));
+        assert_eq!(choose(Policy::KeepMaster, Some(&Value::Null), Some(&paved), "master", "new"), (Some(Value::Null), String::from("master")));
+        assert_eq!(choose(Policy::PreferNew, Some(&paved), None, "master", "new"), (Some(paved.clone()), String::from("master")));
+    }
+}
@@ -0,0 +1,629 @@
+use crate::pg::{Table, InputTable, Network};
+use crate::{Tokens, Name, Names, Context, pg};
+use crate::stream::{GeoStream, NetStream};
+use crate::text::linker;
+use rayon::prelude::*;
+use crate::filter;
+use std::thread;
+use std::convert::TryInto;
+
+pub mod geometric;
+pub mod decision;
+pub mod merge;
+
+use decision::{Action, Decision, Method};
+use merge::{Merge, Policy};
+
+/// Minimum fraction of a portion of a new road within the buffer of a master road that must be
+/// within half the buffer for the portion to run alongside, rather than across, the master road
+const ALONGSIDE: f64 = 0.75;
+
+#[derive(Serialize, Deserialize)]
+pub struct DbSerial {
+    id: i64,
+    names: Vec<Name>,
+    geom: serde_json::Value,
+    distance: f64
+}
+
+pub struct DbType {
+    id: i64,
+    names: Names
+}
+
+pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, args: &clap_v3::ArgMatches) {
+    let iso = args.value_of("iso").unwrap().to_string().to_lowercase();
+
+    let langs: Vec<String> = args.value_of("langs").unwrap().to_string().to_lowercase().split(',').map(|i| {
+        String::from(i.trim())
+    }).collect();
+
+    let output = args.value_of("output").unwrap().to_string();
+
+    let context = Context::new(iso, None, Tokens::generate(langs));
+
+    let master_src = args.value_of("MASTER").unwrap().to_string();
+    let master_source = source(&master_src);
+
+    // Sources are conflated in the order they are given, each into the
+    // master network along with the roads added by the sources before it
+    let new_srcs: Vec<(String, String)> = args.values_of("NEW").unwrap().map(|new_src| {
+        (new_src.to_string(), source(new_src))
+    }).collect();
+
+    for (i, (_, label)) in new_srcs.iter().enumerate() {
+        if *label == master_source || new_srcs.iter().skip(i + 1).any(|(_, other)| other == label) {
+            panic!("Source {} is given more than once, source file names must be unique", label);
+        }
+    }
+
+    let decisions = args.value_of("decisions").map(String::from);
+
+    let merge: Merge = match args.value_of("merge") {
+        None => Merge::default(),
+        Some(merge) => match Merge::from_file(merge) {
+            Ok(merge) => merge,
+            Err(err) => panic!("{}", err)
+        }
+    };
+
+    let buffer: i64 = match args.value_of("BUFFER") {
+        None => 25,
+        Some(buffer) => match buffer.parse::<i64>() {
+            Ok(buffer) => buffer,
+            _ => panic!("--buffer value must be an integer")
+        }
+    };
+
+    let master = Network::new("master");
+    let new = Network::new("new");
+
+    println!("ok - formatted database");
+
+    let mut manager = Vec::with_capacity(2);
+    {
+        let mut db = pool.get().unwrap();
+        let master = master.clone();
+        manager.push(thread::spawn(move || {
+            master.create(&mut db);
+            master.input(&mut db, NetStream::new(
+                GeoStream::new(Some(master_src)),
+                Some(String::from("/tmp/master_error.log")))
+            );
+            tag(&mut db, &master, &master_source);
+            surface(&mut db, &master);
+            master.index(&mut db);
+            master.seq(&mut db);
+            println!("ok - imported {} master lines", master.count(&mut db));
+
+        }));
+    }
+
+    {
+        let mut db = pool.get().unwrap();
+        let new = new.clone();
+        let new_srcs = new_srcs.clone();
+        manager.push(thread::spawn(move || {
+            new.create(&mut db);
+            for (new_src, label) in new_srcs {
+                new.input(&mut db, NetStream::new(
+                    GeoStream::new(Some(new_src)),
+                    Some(String::from("/tmp/new_error.log")))
+                );
+                tag(&mut db, &new, &label);
+            }
+            surface(&mut db, &new);
+            new.index(&mut db);
+            new.seq(&mut db);
+            println!("ok - imported {} new lines", new.count(&mut db));
+
+        }));
+    }
+
+    for thread in manager {
+        thread.join().unwrap();
+    }
+
+    name(&pool, &master, &context);
+    name(&pool, &new, &context);
+
+    if decisions.is_some() {
+        decision::create(&mut pool.get().unwrap());
+    }
+
+    let priority: Vec<String> = new_srcs.iter().map(|(_, label)| label.to_string()).collect();
+
+    for (_, label) in new_srcs.iter() {
+        // Roads of a source are conflated in parallel, so are only compared with the master
+        // network as it was before the source, rather than the roads being added alongside them
+        let snapshot: i64 = match pool.get().unwrap().query("
+            SELECT
+                COALESCE(Max(id), 0)
+            FROM
+                master
+        ", &[]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.get(0).unwrap().get(0)
+        };
+
+        let ids: Vec<i64> = match pool.get().unwrap().query("
+            SELECT
+                id
+            FROM
+                new
+            WHERE
+                source = $1
+        ", &[&label]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.iter().map(|row| row.get(0)).collect()
+        };
+
+        ids.into_par_iter().for_each(|i| {
+            let mut db = pool.get().unwrap();
+
+            let decision = conflate(&mut db, i, snapshot, buffer, label, &priority, &merge);
+
+            if decisions.is_some() {
+                decision.record(&mut db);
+            }
+        });
+
+        println!("ok - conflated {}", label);
+    }
+
+    if let Some(decisions) = decisions {
+        decision::write(&pool, &decisions);
+    }
+
+    let mut output = std::fs::File::create(output).unwrap();
+
+    let mut stream = pg::stream::PGStream::new(pool.get().unwrap(), String::from("next"), format!("
+        DECLARE next CURSOR FOR
+            SELECT
+                json_build_object(
+                    'type', 'Feature',
+                    'properties', props || JSONB_Build_Object('{source}', source),
+                    'geometry', ST_AsGeoJSON(geom)::JSON
+                )::TEXT
+            FROM
+                master
+    ", source = merge::SOURCE), &[]).unwrap();
+
+    std::io::copy(&mut stream, &mut output).unwrap();
+}
+
+///
+/// Conflate a single road of the given source into the master network, returning
+/// the decision that was made. Only master roads with an id no greater than the
+/// snapshot are compared with the road
+///
+fn conflate(db: &mut postgres::Client, i: i64, snapshot: i64, buffer: i64, source: &str, priority: &[String], merge: &Merge) -> Decision {
+    let rows = match db.query(format!("
+        SELECT
+            new.props,
+            new.name,
+            Array_To_Json((Array_Agg(
+                JSON_Build_Object(
+                    'id', master.id,
+                    'names', master.name,
+                    'geom', ST_AsGeoJSON(master.geom)::JSON,
+                    'distance', ST_Distance(master.geom::GEOGRAPHY, new.geom::GEOGRAPHY)
+                )
+                ORDER BY ST_Distance(master.geom, new.geom)
+            ))[:10]) AS nets
+        FROM
+            master
+                INNER JOIN new
+                    ON ST_DWithin(master.geom, new.geom, 0.001)
+        WHERE
+            new.id = $1
+            AND master.id <= $2
+        GROUP BY
+            new.id,
+            new.name,
+            new.props,
+            new.geom
+    ").as_str(), &[&i, &snapshot]) {
+        Err(err) => panic!("{}", err.to_string()),
+        Ok(rows) => rows
+    };
+
+    let row = match rows.get(0) {
+        Some(row) => row,
+        None => {
+            // Inner join failed to return any results - meaning new item
+            // does not have existing roads near it
+            insert(db, i, None);
+            return Decision::new(i, Action::Added);
+        }
+    };
+
+    let props: serde_json::Value = row.get(0);
+    let props = match props {
+        serde_json::Value::Object(props) => props,
+        _ => panic!("props must be an object")
+    };
+
+    let names: serde_json::Value = row.get(1);
+    let names: Vec<Name> = serde_json::from_value(names).unwrap();
+    let names = Names { names: names };
+
+    let nets: Option<serde_json::Value> = row.get(2);
+    let nets: Vec<DbSerial> = match nets {
+        None => {
+            insert(db, i, None);
+            return Decision::new(i, Action::Added);
+        },
+        Some(nets) => match serde_json::from_value(nets) {
+            Err(err) => panic!("JSON Failure: {}", err.to_string()),
+            Ok(nets) => nets
+        }
+    };
+
+    let distances: Vec<(i64, f64)> = nets.iter().map(|net| (net.id, net.distance)).collect();
+
+    // Only the portions of the new road beyond the buffer of the master roads are missing,
+    // the retained portion already being mapped by the master network
+    let split = split(db, i, snapshot, buffer);
+
+    let mut decision = Decision::new(i, Action::new(split.missing.is_some(), split.retained.is_some(), false));
+    decision.coverage = Some(split.coverage);
+    decision.distance = distances.first().map(|(_, distance)| *distance);
+
+    let retained = match split.retained {
+        None => {
+            insert(db, i, None);
+            return decision;
+        },
+        Some(retained) => retained
+    };
+
+    if let Some(ref missing) = split.missing {
+        insert(db, i, Some(missing));
+    }
+
+    let named = props.contains_key("name") && !props.get("name").unwrap().is_null();
+
+    let matched: Option<(i64, Method, Option<f64>)> = if !named {
+        // Roads without names can only be compared by their geometry
+        let candidates: Vec<(i64, geo::MultiLineString<f64>)> = nets.into_iter().filter_map(|net| {
+            let id = net.id;
+            lines(net.geom).map(|geom| (id, geom))
+        }).collect();
+
+        match lines(retained) {
+            Some(geom) => geometric::best(&geom, &candidates, buffer as f64).map(|(id, _)| (id, Method::Geometry, None)),
+            None => None
+        }
+    } else {
+        let mut pnets: Vec<DbType> = Vec::with_capacity(nets.len());
+        for net in nets {
+            pnets.push(DbType {
+                id: net.id,
+                names: Names {
+                    names: net.names
+                }
+            });
+        }
+
+        let primary = linker::Link::new(i, &names);
+        let potentials: Vec<linker::Link> = pnets.iter().map(|net| {
+            linker::Link::new(net.id, &net.names)
+        }).collect();
+
+        linker::linker(primary, potentials, false).map(|link_match| (link_match.id, Method::Name, Some(link_match.score)))
+    };
+
+    let (id, method, score) = match matched {
+        None => {
+            return decision;
+        },
+        Some(matched) => matched
+    };
+
+    decision.action = Action::new(split.missing.is_some(), true, true);
+    decision.master = Some(id);
+    decision.method = Some(method.clone());
+    decision.score = score;
+    decision.distance = distances.iter().find(|(net, _)| *net == id).map(|(_, distance)| *distance);
+
+    // Roads matched by name take the attributes of the new road, other than those from
+    // a network of higher priority, whereas roads matched by geometry alone only gain the
+    // attributes they are missing, unless the merge policy says otherwise
+    let fallback = match method {
+        Method::Name => Policy::PreferNew,
+        Method::Geometry => Policy::PreferNonNull
+    };
+
+    let mut trans = db.transaction().unwrap();
+
+    let (current, origin): (serde_json::Value, String) = match trans.query("
+        SELECT
+            props,
+            source
+        FROM
+            master
+        WHERE
+            id = $1
+        FOR UPDATE
+    ", &[&id]) {
+        Err(err) => panic!("{}", err.to_string()),
+        Ok(rows) => {
+            let row = rows.get(0).unwrap();
+            (row.get(0), row.get(1))
+        }
+    };
+
+    let current = match current {
+        serde_json::Value::Object(current) => current,
+        _ => panic!("props must be an object")
+    };
+
+    let merged = serde_json::Value::from(merge.merge(&current, &props, &origin, source, priority, fallback));
+
+    trans.execute(r#"
+        UPDATE master
+            SET
+                props = $2 || '{ "conflated": true }'::JSONB
+            WHERE
+                id = $1
+    "#, &[&id, &merged]).unwrap();
+
+    trans.commit().unwrap();
+
+    decision
+}
+
+///
+/// The portions of a new road outside & within the buffer of the nearby master roads,
+/// as GeoJSON geometries
+///
+struct Split {
+    missing: Option<serde_json::Value>,
+    retained: Option<serde_json::Value>,
+
+    /// Fraction of the length of the new road within the buffer
+    coverage: f64
+}
+
+///
+/// Split a new road against the buffer of the nearby master roads. Missing portions shorter
+/// than the buffer, such as where the new road overshoots the end of a master road, are ignored.
+///
+/// Portions of the new road within the buffer that only cross or meet a master road are kept
+/// with the missing portions, rather than leaving a gap either side of the crossing. A portion
+/// runs across a master road when it touches the road & less than ALONGSIDE of its length is within
+/// half the buffer of the master roads, a straight crossing having half of its length within half the buffer
+/// whatever the angle it crosses at
+///
+fn split(db: &mut postgres::Client, id: i64, snapshot: i64, buffer: i64) -> Split {
+    match db.query(format!("
+        SELECT
+            CASE
+                WHEN parts.missing IS NULL OR ST_IsEmpty(parts.missing) THEN NULL
+                ELSE ST_AsGeoJSON(parts.missing)::JSON
+            END,
+            CASE
+                WHEN parts.retained IS NULL OR ST_IsEmpty(parts.retained) THEN NULL
+                ELSE ST_AsGeoJSON(parts.retained)::JSON
+            END,
+            CASE
+                WHEN parts.retained IS NULL OR parts.length = 0 THEN 0.0
+                ELSE LEAST(ST_Length(parts.retained::GEOGRAPHY) / parts.length, 1.0)
+            END
+        FROM (
+            SELECT
+                (
+                    SELECT
+                        ST_Multi(ST_Collect(part.geom))
+                    FROM
+                        ST_Dump(ST_LineMerge((
+                            SELECT
+                                ST_Collect(outside.geom)
+                            FROM (
+                                SELECT (ST_Dump(ST_CollectionExtract(ST_Difference(new.geom, covered.geom), 2))).geom
+                                UNION ALL
+                                SELECT (ST_Dump(inside.crossing)).geom
+                            ) AS outside
+                        ))) AS part
+                    WHERE
+                        ST_Length(part.geom::GEOGRAPHY) >= {buffer}
+                ) AS missing,
+                inside.alongside AS retained,
+                ST_Length(new.geom::GEOGRAPHY) AS length
+            FROM
+                new,
+                LATERAL (
+                    SELECT
+                        ST_Union(ST_Buffer(master.geom::GEOGRAPHY, {buffer})::GEOMETRY) AS geom,
+                        ST_Union(ST_Buffer(master.geom::GEOGRAPHY, {buffer} / 2.0)::GEOMETRY) AS half,
+                        ST_Collect(master.geom) AS lines
+                    FROM
+                        master
+                    WHERE
+                        ST_DWithin(master.geom, new.geom, 0.001)
+                        AND master.id <= $2
+                ) AS covered,
+                LATERAL (
+                    SELECT
+                        ST_Multi(ST_Collect(piece.geom) FILTER (WHERE NOT piece.crossing)) AS alongside,
+                        ST_Multi(ST_Collect(piece.geom) FILTER (WHERE piece.crossing)) AS crossing
+                    FROM (
+                        SELECT
+                            part.geom,
+                            ST_Intersects(part.geom, covered.lines)
+                            AND ST_Length(ST_CollectionExtract(ST_Intersection(part.geom, covered.half), 2)::GEOGRAPHY)
+                                < {alongside} * ST_Length(part.geom::GEOGRAPHY) AS crossing
+                        FROM
+                            ST_Dump(ST_LineMerge(ST_CollectionExtract(ST_Intersection(new.geom, covered.geom), 2))) AS part
+                    ) AS piece
+                ) AS inside
+            WHERE
+                new.id = $1
+        ) AS parts
+    ",
+        buffer = &buffer,
+        alongside = ALONGSIDE
+    ).as_str(), &[&id, &snapshot]) {
+        Err(err) => panic!("{}", err.to_string()),
+        Ok(rows) => match rows.get(0) {
+            None => Split {
+                missing: None,
+                retained: None,
+                coverage: 0.0
+            },
+            Some(row) => Split {
+                missing: row.get(0),
+                retained: row.get(1),
+                coverage: row.get(2)
+            }
+        }
+    }
+}
+
+///
+/// Add a new road to the master network, or only the given missing portion of it
+///
+fn insert(db: &mut postgres::Client, id: i64, missing: Option<&serde_json::Value>) {
+    match missing {
+        None => {
+            db.execute(r#"
+                INSERT INTO master (
+                    name,
+                    props,
+                    geom,
+                    source
+                ) SELECT
+                    name,
+                    props || '{ "added": true }'::JSONB,
+                    geom,
+                    source
+                FROM
+                    new
+                WHERE
+                    id = $1
+            "#, &[&id]).unwrap();
+        },
+        Some(missing) => {
+            db.execute(r#"
+                INSERT INTO master (
+                    name,
+                    props,
+                    geom,
+                    source
+                ) SELECT
+                    name,
+                    props || '{ "added": true, "split": true }'::JSONB,
+                    ST_Multi(ST_SetSRID(ST_GeomFromGeoJSON($2), 4326)),
+                    source
+                FROM
+                    new
+                WHERE
+                    id = $1
+            "#, &[&id, &missing.to_string()]).unwrap();
+        }
+    };
+}
+
+///
+/// Parse a GeoJSON (Multi)LineString geometry
+///
+fn lines(geom: serde_json::Value) -> Option<geo::MultiLineString<f64>> {
+    let geom: geojson::Geometry = match serde_json::from_value(geom) {
+        Ok(geom) => geom,
+        Err(_) => { return None; }
+    };
+
+    let value = match geom.value {
+        geojson::Value::LineString(ln) => geojson::Value::MultiLineString(vec![ln]),
+        geojson::Value::MultiLineString(mln) => geojson::Value::MultiLineString(mln),
+        _ => { return None; }
+    };
+
+    value.try_into().ok()
+}
+
+fn name(pool: &r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, table: &(impl Table + std::marker::Sync), context: &Context) {
+    let max = table.max(&mut pool.get().unwrap()).unwrap();
+
+    (1..=max).into_par_iter().for_each(|i| {
+        let mut db = pool.get().unwrap();
+
+        let props = table.props(&mut db, i);
+
+        if !props.contains_key("name") || props.get("name").unwrap().is_null() {
+            db.execute(format!("
+                UPDATE {table}
+                    SET
+                        name = '[]'::JSONB
+                WHERE
+                    id = $1
+            ",
+                table = table.name()
+            ).as_str(), &[&i]).unwrap();
+        } else {
+            let names: Vec<Name> = props.get("name").unwrap().as_str().unwrap().split(";").map(|s| {
+                Name::new(s, 0, None, &context)
+            }).collect();
+
+            let names = serde_json::to_value(names).unwrap();
+
+            db.execute(format!("
+                UPDATE {table}
+                    SET
+                        name = $2::JSONB
+                WHERE
+                    id = $1
+            ",
+                table = table.name()
+            ).as_str(), &[&i, &names]).unwrap();
+        }
+    });
+}
+
+///
+/// Return the name of a source, being its file name without the extension
+///
+fn source(path: &str) -> String {
+    match std::path::Path::new(path).file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => path.to_string()
+    }
+}
+
+///
+/// Record the source of the roads just imported into a network
+///
+fn tag(db: &mut postgres::Client, table: &impl Table, source: &str) {
+    db.execute(format!("
+        ALTER TABLE {table}
+            ADD COLUMN IF NOT EXISTS source TEXT
+    ",
+        table = table.name()
+    ).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        UPDATE {table}
+            SET
+                source = $1
+            WHERE
+                source IS NULL
+    ",
+        table = table.name()
+    ).as_str(), &[&source]).unwrap();
+}
+
+fn surface(db: &mut postgres::Client, table: &impl Table) {
+    let rejects = filter::reject_surface();
+
+    for reject in rejects {
+        db.execute(format!("
+            DELETE FROM
+                {table}
+            WHERE
+                props->>'surface' = $1
+        ",
+            table = table.name()
+        ).as_str(), &[&reject.to_string()]).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+use crate::calc::runs::Runs;
+use crate::pg;
+
+///
+/// Compare the results of two calc runs, writing the change in covered population & RAI
+/// of each area, and the pixels whose coverage changed for display by the viz module
+///
+pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, args: &clap_v3::ArgMatches) {
+    let (to_iso, to_run) = match parse(args.value_of("TO").unwrap()) {
+        Ok(run) => run,
+        Err(err) => panic!("{}", err)
+    };
+
+    let (from_iso, from_run) = match parse(args.value_of("FROM").unwrap()) {
+        Ok(run) => run,
+        Err(err) => panic!("{}", err)
+    };
+
+    let buffer: Option<i64> = match args.value_of("buffer") {
+        None => None,
+        Some(buffer) => match buffer.parse() {
+            Ok(buffer) => Some(buffer),
+            Err(_) => panic!("--buffer value must be an integer")
+        }
+    };
+
+    let mut db = pool.get().unwrap();
+
+    let to_run = match resolve(&Runs::new(&to_iso).finished(&mut db), to_run, None) {
+        Ok(run) => run,
+        Err(err) => panic!("{} {}", &to_iso, err)
+    };
+
+    // When comparing runs of the same country, the latest run before the run compared against is the default
+    let before = if from_iso == to_iso { Some(to_run) } else { None };
+    let from_run = match resolve(&Runs::new(&from_iso).finished(&mut db), from_run, before) {
+        Ok(run) => run,
+        Err(err) => panic!("{} {}", &from_iso, err)
+    };
+
+    println!("ok - comparing {}:{} to {}:{}", &from_iso, from_run, &to_iso, to_run);
+
+    let mut wtr = csv::Writer::from_path(args.value_of("output").unwrap()).unwrap();
+    wtr.write_record(&HEADER).unwrap();
+
+    match db.query(format!("
+        SELECT
+            COALESCE(after.id, before.id),
+            COALESCE(after.parent, before.parent),
+            COALESCE(after.level, before.level),
+            COALESCE(after.name, before.name),
+            COALESCE(after.buffer, before.buffer),
+            COALESCE(after.class, before.class),
+            before.covered,
+            before.rural,
+            after.covered,
+            after.rural
+        FROM
+            (
+                SELECT
+                    *
+                FROM
+                    country_{from}.run_results
+                WHERE
+                    run = $1
+                    AND ($3::BIGINT IS NULL OR buffer = $3)
+            ) AS before
+                FULL OUTER JOIN (
+                    SELECT
+                        *
+                    FROM
+                        country_{to}.run_results
+                    WHERE
+                        run = $2
+                        AND ($3::BIGINT IS NULL OR buffer = $3)
+                ) AS after
+                    ON before.id = after.id
+                    AND before.buffer = after.buffer
+                    AND before.class = after.class
+        ORDER BY
+            after.ord NULLS LAST,
+            before.ord
+    ", from = &from_iso, to = &to_iso).as_str(), &[&from_run, &to_run, &buffer]) {
+        Err(err) => panic!("{}", err),
+        Ok(rows) => {
+            for row in rows.iter() {
+                let level: i64 = row.get(2);
+                let buffer: i64 = row.get(4);
+
+                wtr.write_record(&record(
+                    &[row.get(0), row.get(1), level.to_string(), row.get(3), buffer.to_string(), row.get(5)],
+                    totals(row.get(6), row.get(7)),
+                    totals(row.get(8), row.get(9))
+                )).unwrap();
+            }
+
+            println!("ok - wrote change of {} results", rows.len());
+        }
+    };
+
+    // Pixels are matched by their geometry, so only pixels of the same population grid are compared
+    db.execute(format!("
+        DROP TABLE IF EXISTS country_{to}.diff
+    ", to = &to_iso).as_str(), &[]).unwrap();
+
+    db.execute(format!("
+        CREATE TABLE country_{to}.diff AS
+            WITH before AS (
+                SELECT
+                    cov.buffer,
+                    cov.coverage,
+                    px.geom
+                FROM
+                    country_{from}.run_coverage AS cov
+                        INNER JOIN country_{from}.runs AS run
+                            ON cov.run = run.id
+                        INNER JOIN country_{from}.run_pixels AS px
+                            ON px.grid = run.grid
+                            AND px.id = cov.id
+                WHERE
+                    cov.run = $1
+            ), after AS (
+                SELECT
+                    cov.buffer,
+                    cov.coverage,
+                    px.pop,
+                    px.urban,
+                    px.geom
+                FROM
+                    country_{to}.run_coverage AS cov
+                        INNER JOIN country_{to}.runs AS run
+                            ON cov.run = run.id
+                        INNER JOIN country_{to}.run_pixels AS px
+                            ON px.grid = run.grid
+                            AND px.id = cov.id
+                WHERE
+                    cov.run = $2
+            )
+            SELECT
+                after.buffer,
+                after.pop,
+                after.urban,
+                before.coverage AS before,
+                after.coverage AS after,
+                after.coverage - before.coverage AS change,
+                CASE
+                    WHEN after.urban THEN 0.0
+                    ELSE after.pop * (after.coverage - before.coverage) * 0.01
+                END AS covered,
+                after.geom
+            FROM
+                before
+                    INNER JOIN after
+                        ON before.geom ~= after.geom
+                        AND before.buffer = after.buffer
+            WHERE
+                before.coverage != after.coverage
+                AND ($3::BIGINT IS NULL OR after.buffer = $3)
+    ", from = &from_iso, to = &to_iso).as_str(), &[&from_run, &to_run, &buffer]).unwrap();
+
+    db.execute(format!("
+        CREATE INDEX diff_gix
+            ON country_{to}.diff USING GIST (geom)
+    ", to = &to_iso).as_str(), &[]).unwrap();
+
+    match db.query(format!("
+        SELECT
+            count(*) FILTER (WHERE change > 0),
+            count(*) FILTER (WHERE change < 0)
+        FROM
+            country_{to}.diff
+    ", to = &to_iso).as_str(), &[]) {
+        Err(err) => panic!("{}", err),
+        Ok(res) => {
+            let improved: i64 = res.get(0).unwrap().get(0);
+            let worsened: i64 = res.get(0).unwrap().get(1);
+            println!("ok - coverage improved in {} pixels & worsened in {} pixels", improved, worsened);
+        }
+    };
+
+    if let Some(pixels) = args.value_of("pixels") {
+        let mut output = std::fs::File::create(pixels).unwrap();
+
+        let mut stream = pg::stream::PGStream::new(pool.get().unwrap(), String::from("next"), format!("
+            DECLARE next CURSOR FOR
+                SELECT
+                    json_build_object(
+                        'type', 'Feature',
+                        'properties', json_build_object(
+                            'buffer', buffer,
+                            'population', pop,
+                            'urban', urban,
+                            'before', before,
+                            'after', after,
+                            'change', change,
+                            'covered', covered
+                        ),
+                        'geometry', ST_AsGeoJSON(geom)::JSON
+                    )::TEXT
+                FROM
+                    country_{to}.diff
+        ", to = &to_iso), &[]).unwrap();
+
+        std::io::copy(&mut stream, &mut output).unwrap();
+        println!("ok - wrote changed pixels to {}", pixels);
+    }
+}
+
+///
+/// Columns of the CSV output
+///
+const HEADER: [&str; 12] = [
+    "id",
+    "parent",
+    "level",
+    "name",
+    "buffer",
+    "class",
+    "covered population before",
+    "covered population after",
+    "covered population change",
+    "rai before",
+    "rai after",
+    "rai change"
+];
+
+///
+/// Parse a run given in the form ISO[:RUN]
+///
+fn parse(input: &str) -> Result<(String, Option<i32>), String> {
+    let mut parts = input.splitn(2, ':');
+
+    let iso = parts.next().unwrap().to_lowercase();
+    if iso.is_empty() || !iso.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("{} must be in the form ISO[:RUN]", input));
+    }
+
+    match parts.next() {
+        None => Ok((iso, None)),
+        Some(run) => match run.parse() {
+            Ok(run) => Ok((iso, Some(run))),
+            Err(_) => Err(format!("{} must be in the form ISO[:RUN]", input))
+        }
+    }
+}
+
+///
+/// Return the given run if it finished, otherwise the latest finished run,
+/// or the latest finished run before another run
+///
+fn resolve(finished: &Vec<i32>, run: Option<i32>, before: Option<i32>) -> Result<i32, String> {
+    match run {
+        Some(run) if finished.contains(&run) => Ok(run),
+        Some(run) => Err(format!("run {} does not exist or did not finish", run)),
+        None => match finished.iter().filter(|id| before.map_or(true, |before| **id < before)).last() {
+            Some(run) => Ok(*run),
+            None => Err(String::from("has no finished run to compare"))
+        }
+    }
+}
+
+///
+/// Covered & rural population of an area within a run, if the area exists within the run
+///
+fn totals(covered: Option<f64>, rural: Option<f64>) -> Option<(f64, f64)> {
+    match (covered, rural) {
+        (Some(covered), Some(rural)) => Some((covered, rural)),
+        _ => None
+    }
+}
+
+///
+/// Return a row of the change report from the covered & rural population of an area before & after
+///
+fn record(area: &[String; 6], before: Option<(f64, f64)>, after: Option<(f64, f64)>) -> Vec<String> {
+    let rai = |totals: Option<(f64, f64)>| match totals {
+        Some((covered, rural)) if rural > 0.0 => Some(covered / rural * 100.0),
+        _ => None
+    };
+
+    let format = |value: Option<f64>| value.map(|value| value.to_string()).unwrap_or_default();
+
+    let change = |before: Option<f64>, after: Option<f64>| match (before, after) {
+        (Some(before), Some(after)) => Some(after - before),
+        _ => None
+    };
+
+    let mut record: Vec<String> = area.to_vec();
+    record.extend(vec![
+        format(before.map(|before| before.0)),
+        format(after.map(|after| after.0)),
+        format(change(before.map(|before| before.0), after.map(|after| after.0))),
+        format(rai(before)),
+        format(rai(after)),
+        format(change(rai(before), rai(after)))
+    ]);
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("PY"), Ok((String::from("py"), None)));
+        assert_eq!(parse("py:12"), Ok((String::from("py"), Some(12))));
+        assert_eq!(parse("py:latest"), Err(String::from("py:latest must be in the form ISO[:RUN]")));
+        assert_eq!(parse("py; DROP"), Err(String::from("py; DROP must be in the form ISO[:RUN]")));
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(&vec![1, 2, 4], None, None), Ok(4));
+        assert_eq!(resolve(&vec![1, 2, 4], None, Some(4)), Ok(2));
+        assert_eq!(resolve(&vec![1, 2, 4], Some(1), Some(4)), Ok(1));
+        assert_eq!(resolve(&vec![1, 2, 4], Some(3), None), Err(String::from("run 3 does not exist or did not finish")));
+        assert_eq!(resolve(&vec![4], None, Some(4)), Err(String::from("has no finished run to compare")));
+    }
+
+    #[test]
+    fn test_record() {
+        let area = [
+            String::from("Central"),
+            String::new(),
+            String::from("1"),
+            String::from("Central"),
+            String::from("2000"),
+            String::from("all")
+        ];
+
+        assert_eq!(record(&area, Some((50.0, 100.0)), Some((75.0, 100.0))), vec![
+            "Central", "", "1", "Central", "2000", "all", "50", "75", "25", "50", "75", "25"
+        ]);
+
+        assert_eq!(record(&area, None, Some((75.0, 100.0))), vec![
+            "Central", "", "1", "Central", "2000", "all", "", "75", "", "", "75", ""
+        ]);
+    }
+}
@@ -1,3 +1,9 @@
+/// Approximate length (metres) of a degree of latitude
+pub const LAT_METRES: f64 = 110_574.0;
+
+/// Approximate length (metres) of a degree of longitude at the equator
+pub const LNG_METRES: f64 = 111_320.0;
+
 #[derive(Debug, PartialEq)]
 pub struct Extent {
     pub minx: f64,
@@ -6,6 +6,8 @@ pub mod list;
 pub mod viz;
 pub mod drop;
 pub mod filter;
+pub mod population;
+pub mod diff;
 
 pub use text::Tokenized;
 pub use text::Tokens;
@@ -19,6 +21,7 @@ mod text;
 mod pg;
 mod mvt;
 mod grid;
+mod raster;
 mod stream;
 mod types;
 
@@ -1,13 +1,20 @@
 
 pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, _args: &clap_v3::ArgMatches) {
-    let countries = list(pool);
+    let countries = list(pool.clone());
 
     if countries.len() == 0 {
         println!("\nNo Countries Loaded\n");
     } else {
+        let mut db = pool.get().unwrap();
+
         println!("\nLoaded Countries:");
         for country in countries {
-            println!("- {}", country);
+            let runs = crate::calc::runs::Runs::new(country.trim_start_matches("country_"));
+
+            match runs.latest(&mut db) {
+                Some(latest) => println!("- {} (latest run: {})", country, latest),
+                None => println!("- {}", country)
+            };
         }
         println!("");
     }
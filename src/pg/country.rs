@@ -1,17 +1,235 @@
 use postgres::Client;
 use geojson::GeoJson;
 use super::Table;
+use crate::grid::Extent;
 
 pub struct Country {
-    name: String
+    name: String,
+
+    /// Optional boundary to load instead of the boundary dataset
+    boundary: Option<Boundary>
 }
 
 impl Country {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, boundary: Option<Boundary>) -> Self {
         Country {
-            name: name
+            name: name,
+            boundary: boundary
+        }
+    }
+}
+
+const BOUNDARIES_URL: &str = "https://raw.githubusercontent.com/datasets/geo-countries/master/data/countries.geojson";
+
+///
+/// A single country boundary from the boundary dataset
+///
+#[derive(Debug, Clone)]
+pub struct Boundary {
+    pub name: String,
+    pub iso: String,
+    pub geom: geojson::Geometry
+}
+
+impl Boundary {
+    ///
+    /// Return the bounding box of the boundary
+    ///
+    pub fn extent(&self) -> Extent {
+        let mut extent = Extent {
+            minx: std::f64::MAX,
+            miny: std::f64::MAX,
+            maxx: std::f64::MIN,
+            maxy: std::f64::MIN
+        };
+
+        let polys = match self.geom.value {
+            geojson::Value::Polygon(ref py) => vec![py.clone()],
+            geojson::Value::MultiPolygon(ref mpy) => mpy.clone(),
+            _ => Vec::new()
+        };
+
+        for coord in polys.iter().flatten().flatten() {
+            extent.minx = extent.minx.min(coord[0]);
+            extent.miny = extent.miny.min(coord[1]);
+            extent.maxx = extent.maxx.max(coord[0]);
+            extent.maxy = extent.maxy.max(coord[1]);
+        }
+
+        extent
+    }
+}
+
+///
+/// Return the boundary of a single country, from the given GeoJSON file if
+/// provided, otherwise from the boundary dataset
+///
+pub fn boundary(iso: &str, source: Option<&str>) -> Boundary {
+    let found = match source {
+        Some(source) => match std::fs::read_to_string(source) {
+            Ok(text) => from_geojson(iso, &text),
+            Err(err) => Err(format!("Unable to read {}: {}", source, err))
+        },
+        None => match boundaries().into_iter().find(|country| country.iso.to_lowercase() == iso.to_lowercase()) {
+            Some(country) => Ok(country),
+            None => Err(format!("{} is not a known country ISO code", iso))
+        }
+    };
+
+    match found {
+        Ok(boundary) => boundary,
+        Err(err) => panic!("{}", err)
+    }
+}
+
+///
+/// Return the boundary dataset, downloading it on first use & caching
+/// it so that subsequent runs can be performed without network access
+///
+pub fn boundaries() -> Vec<Boundary> {
+    let cache = cache();
+
+    if let Some(ref cache) = cache {
+        if let Ok(text) = std::fs::read_to_string(cache) {
+            match parse(&text) {
+                Ok(boundaries) => { return boundaries; },
+                Err(err) => println!("warn - ignoring invalid cached boundaries {}: {}", cache.display(), err)
+            };
         }
     }
+
+    let text = match reqwest::blocking::get(BOUNDARIES_URL).and_then(|res| res.text()) {
+        Ok(text) => text,
+        Err(err) => panic!("Unable to download country boundaries ({}), use --country-boundary or place the boundary dataset at {}",
+            err,
+            cache.map(|cache| cache.display().to_string()).unwrap_or(String::from("$HOME/.cache/rai-toolkit/countries.geojson"))
+        )
+    };
+
+    let boundaries = match parse(&text) {
+        Ok(boundaries) => boundaries,
+        Err(err) => panic!("{}", err)
+    };
+
+    if let Some(cache) = cache {
+        let written = match cache.parent() {
+            Some(dir) => std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&cache, &text)),
+            None => std::fs::write(&cache, &text)
+        };
+
+        if let Err(err) = written {
+            println!("warn - unable to cache boundaries to {}: {}", cache.display(), err);
+        }
+    }
+
+    boundaries
+}
+
+///
+/// Location of the cached boundary dataset, $XDG_CACHE_HOME/rai-toolkit/countries.geojson
+/// falling back to $HOME/.cache/rai-toolkit/countries.geojson
+///
+fn cache() -> Option<std::path::PathBuf> {
+    let dir = match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => std::path::PathBuf::from(dir),
+        _ => match std::env::var("HOME") {
+            Ok(home) if !home.is_empty() => std::path::PathBuf::from(home).join(".cache"),
+            _ => { return None; }
+        }
+    };
+
+    Some(dir.join("rai-toolkit").join("countries.geojson"))
+}
+
+///
+/// Parse the boundary dataset, a GeoJSON FeatureCollection of countries
+/// with ADMIN (name) & ISO_A2 properties
+///
+pub fn parse(text: &str) -> Result<Vec<Boundary>, String> {
+    let countries = match text.parse::<GeoJson>() {
+        Ok(GeoJson::FeatureCollection(fc)) => fc.features,
+        Ok(_) => { return Err(String::from("Countries must be a GeoJSON feature collection")); },
+        Err(err) => { return Err(format!("Invalid countries GeoJSON: {}", err)); }
+    };
+
+    let mut boundaries = Vec::with_capacity(countries.len());
+
+    for country in countries {
+        let props = match country.properties {
+            Some(props) => props,
+            None => { return Err(String::from("Country Feature is missing properties")); }
+        };
+
+        let name: String = match props.get("ADMIN") {
+            Some(name) => match name.as_str() {
+                Some(name) => name.to_string(),
+                None => { return Err(String::from("Country Feature ADMIN property must be string")); }
+            },
+            None => { return Err(String::from("Country Feature is missing ADMIN property")); }
+        };
+
+        let iso: String = match props.get("ISO_A2") {
+            Some(iso) => match iso.as_str() {
+                Some(iso) => iso.to_string(),
+                None => { return Err(String::from("Country Feature ISO_A2 property must be string")); }
+            },
+            None => { return Err(String::from("Country Feature is missing ISO_A2 property")); }
+        };
+
+        let geom = match country.geometry {
+            Some(geom) => geom,
+            None => { return Err(String::from("Country Feature is missing geometry")); }
+        };
+
+        boundaries.push(Boundary {
+            name,
+            iso,
+            geom
+        });
+    }
+
+    Ok(boundaries)
+}
+
+///
+/// Read the boundary of a country from a GeoJSON file. If the file is in the format of
+/// the boundary dataset the matching country is used, otherwise the (Multi)Polygons of
+/// all features in the file are taken to be the boundary of the country, allowing
+/// official national boundaries to be used
+///
+pub fn from_geojson(iso: &str, text: &str) -> Result<Boundary, String> {
+    if let Ok(boundaries) = parse(text) {
+        return match boundaries.into_iter().find(|country| country.iso.to_lowercase() == iso.to_lowercase()) {
+            Some(country) => Ok(country),
+            None => Err(format!("{} is not in the given country boundaries", iso))
+        };
+    }
+
+    let geoms: Vec<geojson::Geometry> = match text.parse::<GeoJson>() {
+        Ok(GeoJson::FeatureCollection(fc)) => fc.features.into_iter().filter_map(|feat| feat.geometry).collect(),
+        Ok(GeoJson::Feature(feat)) => feat.geometry.into_iter().collect(),
+        Ok(GeoJson::Geometry(geom)) => vec![geom],
+        Err(err) => { return Err(format!("Invalid country boundary GeoJSON: {}", err)); }
+    };
+
+    let mut polys: Vec<geojson::PolygonType> = Vec::new();
+    for geom in geoms {
+        match geom.value {
+            geojson::Value::Polygon(py) => polys.push(py),
+            geojson::Value::MultiPolygon(mut mpy) => polys.append(&mut mpy),
+            _ => { return Err(String::from("Country boundary must have (Multi)Polygon geometry")); }
+        };
+    }
+
+    if polys.is_empty() {
+        return Err(String::from("Country boundary contains no (Multi)Polygon geometries"));
+    }
+
+    Ok(Boundary {
+        name: iso.to_uppercase(),
+        iso: iso.to_uppercase(),
+        geom: geojson::Geometry::new(geojson::Value::MultiPolygon(polys))
+    })
 }
 
 impl Table for Country {
@@ -33,41 +251,13 @@ impl Table for Country {
             )
         ", &self.name).as_str(), &[]).unwrap();
 
-        let countries = reqwest::blocking::get("https://raw.githubusercontent.com/datasets/geo-countries/master/data/countries.geojson").unwrap()
-            .text().unwrap();
-
-        let countries = match countries.parse::<GeoJson>().unwrap() {
-            GeoJson::FeatureCollection(fc) => fc.features,
-            _ => panic!("Countries must be a GeoJSON feature collection")
+        // Only the given country is loaded when boundaries are overridden
+        let boundaries = match self.boundary {
+            None => boundaries(),
+            Some(ref boundary) => vec![boundary.clone()]
         };
 
-        for country in countries {
-            let props = match country.properties {
-                Some(props) => props,
-                None => panic!("Country Feature is missing properties")
-            };
-
-            let name: String = match props.get("ADMIN") {
-                Some(name) => match name.as_str() {
-                    Some(name) => name.to_string(),
-                    None => panic!("Country Feature ADMIN property must be string")
-                },
-                None => panic!("Country Feature is missing ADMIN property")
-            };
-
-            let iso: String = match props.get("ISO_A2") {
-                Some(iso) => match iso.as_str() {
-                    Some(iso) => iso.to_string(),
-                    None => panic!("Country Feature ISO_A2 property must be string")
-                },
-                None => panic!("Country Feature is missing ISO_A2 property")
-            };
-
-            let geom: String = match country.geometry {
-                Some(geom) => geom.to_string(),
-                None => panic!("Country Feature is missing geometry")
-            };
-
+        for country in boundaries {
             conn.query(format!("
                 INSERT INTO {} (
                     name,
@@ -79,9 +269,9 @@ impl Table for Country {
                     ST_SetSRID(ST_Multi(ST_GeomFromGeoJSON($3)), 4326)
                 )
             ", &self.name).as_str(), &[
-                &name,
-                &iso,
-                &geom
+                &country.name,
+                &country.iso,
+                &country.geom.to_string()
             ]).unwrap();
         }
     }
@@ -126,3 +316,63 @@ impl Table for Country {
         ", name = &self.name).as_str(), &[]).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTRIES: &str = r#"{
+        "type": "FeatureCollection",
+        "features": [{
+            "type": "Feature",
+            "properties": { "ADMIN": "Paraguay", "ISO_A2": "PY" },
+            "geometry": { "type": "Polygon", "coordinates": [[[-62.6, -27.6], [-54.3, -27.6], [-54.3, -19.3], [-62.6, -19.3], [-62.6, -27.6]]] }
+        }]
+    }"#;
+
+    #[test]
+    fn test_parse() {
+        let boundaries = parse(COUNTRIES).unwrap();
+
+        assert_eq!(boundaries.len(), 1);
+        assert_eq!(boundaries[0].name, "Paraguay");
+        assert_eq!(boundaries[0].iso, "PY");
+        assert_eq!(boundaries[0].extent(), Extent {
+            minx: -62.6,
+            miny: -27.6,
+            maxx: -54.3,
+            maxy: -19.3
+        });
+
+        assert!(parse(r#"{ "type": "Point", "coordinates": [0, 0] }"#).is_err());
+    }
+
+    #[test]
+    fn test_from_geojson() {
+        assert_eq!(from_geojson("py", COUNTRIES).unwrap().name, "Paraguay");
+        assert!(from_geojson("bo", COUNTRIES).is_err());
+
+        let official = from_geojson("py", r#"{
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": { "name": "Region A" },
+                "geometry": { "type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]] }
+            }, {
+                "type": "Feature",
+                "properties": { "name": "Region B" },
+                "geometry": { "type": "Polygon", "coordinates": [[[2, 2], [3, 2], [3, 3], [2, 2]]] }
+            }]
+        }"#).unwrap();
+
+        assert_eq!(official.iso, "PY");
+        assert_eq!(official.extent(), Extent {
+            minx: 0.0,
+            miny: 0.0,
+            maxx: 3.0,
+            maxy: 3.0
+        });
+
+        assert!(from_geojson("py", r#"{ "type": "Point", "coordinates": [0, 0] }"#).is_err());
+    }
+}
@@ -8,9 +8,11 @@ pub mod network;
 pub mod country;
 pub mod stream;
 pub mod polygon;
+pub mod population;
 pub use self::polygon::Polygon;
 pub use self::network::Network;
 pub use self::country::Country;
+pub use self::population::Population;
 
 pub trait Table {
     fn create(&self, conn: &mut Client);
@@ -0,0 +1,237 @@
+use postgres::Client;
+use postgres::error::SqlState;
+use crate::raster::Raster;
+use super::Table;
+
+///
+/// A named population dataset, a single product & year of population
+/// raster tiles stored in its own table within the pop schema
+///
+/// Unlike other tables, creating a population table does not drop any
+/// previously loaded data as it is shared by all countries
+///
+pub struct Population {
+    name: String,
+    dataset: String
+}
+
+impl Population {
+    pub fn new(dataset: impl ToString) -> Self {
+        let dataset = dataset.to_string();
+
+        if dataset.is_empty() || !dataset.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            panic!("Population dataset name {} must only contain lowercase letters, numbers & underscores", dataset);
+        }
+
+        Population {
+            name: format!("pop.{}", &dataset),
+            dataset: dataset
+        }
+    }
+
+    ///
+    /// Return the dataset name of a given product & year, ie gpw-v4 & 2020 => gpw_v4_2020
+    ///
+    pub fn dataset(product: &str, year: i32) -> String {
+        let product: String = product.to_lowercase().chars().map(|c| {
+            if c.is_ascii_alphanumeric() { c } else { '_' }
+        }).collect();
+
+        format!("{}_{}", product, year)
+    }
+
+    ///
+    /// Return the name, product & year of all registered datasets
+    ///
+    pub fn list(conn: &mut Client) -> Vec<(String, String, i32)> {
+        match conn.query("
+            SELECT
+                name,
+                product,
+                year
+            FROM
+                pop.datasets
+            ORDER BY
+                name
+        ", &[]) {
+            Err(_) => Vec::new(),
+            Ok(rows) => rows.iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect()
+        }
+    }
+
+    ///
+    /// Record the product & year of the dataset in the pop.datasets registry
+    ///
+    pub fn register(&self, conn: &mut Client, product: &String, year: i32) {
+        conn.execute("
+            INSERT INTO pop.datasets (
+                name,
+                product,
+                year
+            ) VALUES (
+                $1,
+                $2,
+                $3
+            ) ON CONFLICT (name)
+                DO UPDATE SET
+                    product = EXCLUDED.product,
+                    year = EXCLUDED.year
+        ", &[&self.dataset, product, &year]).unwrap();
+    }
+
+    ///
+    /// Return the checksum of the files loaded into a dataset, if any were loaded since
+    /// checksums were recorded
+    ///
+    pub fn checksum(conn: &mut Client, dataset: &str) -> Option<String> {
+        match conn.query("
+            SELECT
+                checksum
+            FROM
+                pop.datasets
+            WHERE
+                name = $1
+        ", &[&dataset]) {
+            // Registries created before checksums were recorded lack the column until the next load
+            Err(ref err) if err.code() == Some(&SqlState::UNDEFINED_TABLE) || err.code() == Some(&SqlState::UNDEFINED_COLUMN) => None,
+            Err(err) => panic!("{}", err),
+            Ok(rows) => rows.get(0).and_then(|row| row.get(0))
+        }
+    }
+
+    ///
+    /// Record the checksum of the files loaded into the dataset, which changes with every
+    /// load so that calc recreates its raster subset whenever the dataset is reloaded
+    ///
+    pub fn loaded(&self, conn: &mut Client, checksum: &str) {
+        conn.execute("
+            UPDATE pop.datasets
+                SET
+                    checksum = $2
+                WHERE
+                    name = $1
+        ", &[&self.dataset, &checksum]).unwrap();
+    }
+
+    ///
+    /// Insert a single tile, replacing any existing tile at the same
+    /// location so that loads can be safely repeated
+    ///
+    pub fn insert(&self, conn: &mut Client, tile: &Raster) {
+        conn.execute(format!("
+            INSERT INTO {name} (
+                rast
+            ) VALUES (
+                $1::TEXT::RASTER
+            ) ON CONFLICT (ST_UpperLeftX(rast), ST_UpperLeftY(rast))
+                DO UPDATE SET rast = EXCLUDED.rast
+        ", name = &self.name).as_str(), &[
+            &tile.to_hex_wkb()
+        ]).unwrap();
+    }
+}
+
+impl Table for Population {
+    fn create(&self, conn: &mut Client) {
+        conn.execute(r#"
+             CREATE EXTENSION IF NOT EXISTS POSTGIS
+        "#, &[]).unwrap();
+
+        conn.execute(r#"
+             CREATE EXTENSION IF NOT EXISTS POSTGIS_RASTER
+        "#, &[]).unwrap();
+
+        conn.execute(r#"
+             CREATE SCHEMA IF NOT EXISTS pop
+        "#, &[]).unwrap();
+
+        conn.execute("
+            CREATE TABLE IF NOT EXISTS pop.datasets (
+                name        TEXT PRIMARY KEY,
+                product     TEXT,
+                year        INT,
+                checksum    TEXT
+            )
+        ", &[]).unwrap();
+
+        conn.execute("
+            ALTER TABLE pop.datasets
+                ADD COLUMN IF NOT EXISTS checksum TEXT
+        ", &[]).unwrap();
+
+        conn.execute(format!("
+            CREATE TABLE IF NOT EXISTS {} (
+                rid     SERIAL PRIMARY KEY,
+                rast    RASTER
+            )
+        ", &self.name).as_str(), &[]).unwrap();
+
+        conn.execute(format!("
+            CREATE UNIQUE INDEX IF NOT EXISTS {}_tile_idx
+                ON {} (ST_UpperLeftX(rast), ST_UpperLeftY(rast))
+        ", &self.dataset, &self.name).as_str(), &[]).unwrap();
+    }
+
+    fn max(&self, db: &mut Client) -> Option<i64> {
+        let max: Option<i32> = match db.query(format!("
+            SELECT
+                MAX(rid)
+            FROM
+                {}
+        ", &self.name).as_str(), &[]) {
+            Err(err) => panic!("{}", err),
+            Ok(res) => res.get(0).unwrap().get(0)
+        };
+
+        max.map(|max| max as i64)
+    }
+
+    fn name(&self) -> &String {
+        &self.name
+    }
+
+    fn count(&self, conn: &mut Client) -> i64 {
+        match conn.query(format!("
+            SELECT count(*) FROM {}
+        ", &self.name).as_str(), &[]) {
+            Ok(res) => {
+                let cnt: i64 = res.get(0).unwrap().get(0);
+                cnt
+            },
+            _ => 0
+        }
+    }
+
+    fn props(&self, _db: &mut Client, _id: i64) -> serde_json::Map<String, serde_json::Value> {
+        serde_json::Map::new()
+    }
+
+    fn index(&self, conn: &mut Client) {
+        conn.execute(format!("
+            CREATE INDEX IF NOT EXISTS {}_st_convexhull_idx
+                ON {} USING GIST (ST_ConvexHull(rast))
+        ", &self.dataset, &self.name).as_str(), &[]).unwrap();
+
+        conn.execute(format!("
+            ANALYZE {}
+        ", &self.name).as_str(), &[]).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dataset() {
+        assert_eq!(Population::dataset("gpw-v4", 2020), "gpw_v4_2020");
+        assert_eq!(Population::dataset("WorldPop", 2015), "worldpop_2015");
+        assert_eq!(Population::new("hrsl_2018").name(), "pop.hrsl_2018");
+    }
+
+    #[test]
+    #[should_panic(expected = "must only contain lowercase letters")]
+    fn test_invalid_dataset() {
+        Population::new("pop.population; DROP TABLE");
+    }
+}
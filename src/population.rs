@@ -0,0 +1,142 @@
+use crate::pg::{Table, Population};
+use crate::raster::Raster;
+use crate::grid::Extent;
+use crate::calc::stages::{fingerprint, file_fingerprint};
+use indicatif::ProgressBar;
+
+pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, args: &clap_v3::ArgMatches) {
+    match args.subcommand() {
+        ("load", Some(sub_args)) => load(pool, sub_args),
+        ("list", Some(_)) => list(pool),
+        _ => {
+            println!("Invalid Subcommand: ./rai-toolkit population --help for valid options");
+            std::process::exit(1);
+        }
+    }
+}
+
+///
+/// Load ESRI ASCII grid & GeoTIFF population files into the dataset of the given
+/// product & year, optionally only loading the portion of each file covering a given country
+///
+fn load(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>, args: &clap_v3::ArgMatches) {
+    let product = args.value_of("product").unwrap().to_string();
+
+    let year: i32 = match args.value_of("year").unwrap().parse() {
+        Ok(year) => year,
+        Err(_) => panic!("--year must be a valid year")
+    };
+
+    let extent: Option<Extent> = match args.value_of("iso") {
+        None => None,
+        Some(iso) => Some(snap(crate::pg::country::boundary(iso, args.value_of("country-boundary")).extent()))
+    };
+
+    let population = Population::new(Population::dataset(&product, year));
+
+    let mut db = pool.get().unwrap();
+    population.create(&mut db);
+    population.register(&mut db, &product, year);
+
+    for path in args.values_of("RASTER").unwrap() {
+        let mut loaded = 0;
+        let pb = ProgressBar::new(0);
+
+        // Files are read a row of tiles at a time, so that global rasters need not fit in memory
+        let intersects = Raster::stream(path, extent.as_ref(), &|header| tile_size(header.scale_x), &mut |band| {
+            let tiles = band.tiles(tile_size(band.scale_x));
+            pb.inc_length(tiles.len() as u64);
+
+            for tile in tiles.iter() {
+                population.insert(&mut db, tile);
+                pb.inc(1);
+            }
+
+            loaded += tiles.len();
+            Ok(())
+        });
+        pb.finish();
+
+        match intersects {
+            Ok(true) => {
+                // Tiles are replaced by each load, so the checksum follows every file loaded & the extent loaded from it
+                let previous = Population::checksum(&mut db, &Population::dataset(&product, year)).unwrap_or_default();
+                let window = extent.as_ref().map(|extent| {
+                    format!("{},{},{},{}", extent.minx, extent.miny, extent.maxx, extent.maxy)
+                }).unwrap_or_default();
+
+                population.loaded(&mut db, &fingerprint(&[&previous, &file_fingerprint(path), &window]));
+                println!("ok - loaded {} tiles from {}", loaded, path);
+            },
+            Ok(false) => println!("ok - {} does not intersect country, skipping", path),
+            Err(err) => panic!("{}", err)
+        };
+    }
+
+    population.index(&mut db);
+    println!("ok - {} population tiles loaded into {}", population.count(&mut db), Population::dataset(&product, year));
+}
+
+///
+/// List the population datasets that are currently loaded
+///
+fn list(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>) {
+    let datasets = Population::list(&mut pool.get().unwrap());
+
+    if datasets.len() == 0 {
+        println!("\nNo Population Datasets Loaded\n");
+    } else {
+        println!("\nLoaded Population Datasets:");
+        for (name, product, year) in datasets {
+            println!("- {} ({} {})", name, product, year);
+        }
+        println!("");
+    }
+}
+
+///
+/// Expand an extent to whole degrees, so that tiles loaded for neighbouring
+/// countries share the same alignment and replace rather than overlap each other
+///
+fn snap(extent: Extent) -> Extent {
+    Extent {
+        minx: extent.minx.floor(),
+        miny: extent.miny.floor(),
+        maxx: extent.maxx.ceil(),
+        maxy: extent.maxy.ceil()
+    }
+}
+
+///
+/// Number of cells along each side of a tile, tiles covering a single degree
+///
+fn tile_size(cellsize: f64) -> usize {
+    ((1.0 / cellsize.abs()).round() as usize).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snap() {
+        assert_eq!(snap(Extent {
+            minx: -62.65,
+            miny: -27.61,
+            maxx: -54.26,
+            maxy: -19.29
+        }), Extent {
+            minx: -63.0,
+            miny: -28.0,
+            maxx: -54.0,
+            maxy: -19.0
+        });
+    }
+
+    #[test]
+    fn test_tile_size() {
+        assert_eq!(tile_size(1.0 / 120.0), 120);
+        assert_eq!(tile_size(0.25), 4);
+        assert_eq!(tile_size(2.0), 1);
+    }
+}
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use crate::grid::Extent;
+use super::{Raster, Header, Bands};
+
+///
+/// Read an ESRI ASCII grid file a band of rows at a time
+///
+pub fn read(path: &str, extent: Option<&Extent>, band: &dyn Fn(&Header) -> usize, emit: &mut dyn FnMut(Raster) -> Result<(), String>) -> Result<bool, String> {
+    match File::open(path) {
+        Ok(file) => bands(BufReader::new(file), extent, band, emit),
+        Err(err) => Err(format!("Unable to open {}: {}", path, err))
+    }
+}
+
+///
+/// Parse an ESRI ASCII grid a band of the given number of rows at a time, only
+/// retaining the cells within the given extent. False is returned if the grid does not
+/// intersect the extent
+///
+/// ncols         4
+/// nrows         2
+/// xllcorner     -180.0
+/// yllcorner     -90.0
+/// cellsize      90.0
+/// NODATA_value  -9999
+/// 0 1 2 3
+/// 4 5 6 -9999
+///
+pub fn bands(input: impl BufRead, extent: Option<&Extent>, band: &dyn Fn(&Header) -> usize, emit: &mut dyn FnMut(Raster) -> Result<(), String>) -> Result<bool, String> {
+    let mut lines = input.lines();
+
+    let mut cols: Option<usize> = None;
+    let mut rows: Option<usize> = None;
+    let mut xll: Option<(f64, bool)> = None;
+    let mut yll: Option<(f64, bool)> = None;
+    let mut cellsize: Option<f64> = None;
+    let mut nodata: Option<f64> = None;
+
+    // Header lines are key/value pairs, the first line starting with a
+    // number is the first row of data
+    let mut first: Option<String> = None;
+    while first.is_none() {
+        let line = match lines.next() {
+            None => { return Err(String::from("ASCII grid has no data")); },
+            Some(Err(err)) => { return Err(err.to_string()); },
+            Some(Ok(line)) => line
+        };
+
+        let mut parts = line.split_whitespace();
+        let key = match parts.next() {
+            None => continue,
+            Some(key) => key.to_lowercase()
+        };
+
+        let value = match parts.next() {
+            None => None,
+            Some(value) => match value.parse::<f64>() {
+                Ok(value) => Some(value),
+                Err(_) => { return Err(format!("Invalid ASCII grid header value: {}", line)); }
+            }
+        };
+
+        match (key.as_str(), value) {
+            ("ncols", Some(value)) => cols = Some(value as usize),
+            ("nrows", Some(value)) => rows = Some(value as usize),
+            ("xllcorner", Some(value)) => xll = Some((value, false)),
+            ("xllcenter", Some(value)) => xll = Some((value, true)),
+            ("yllcorner", Some(value)) => yll = Some((value, false)),
+            ("yllcenter", Some(value)) => yll = Some((value, true)),
+            ("cellsize", Some(value)) => cellsize = Some(value),
+            ("nodata_value", Some(value)) => nodata = Some(value),
+            _ => {
+                if key.parse::<f64>().is_ok() {
+                    first = Some(line);
+                } else {
+                    return Err(format!("Invalid ASCII grid header: {}", line));
+                }
+            }
+        };
+    }
+
+    let (cols, rows, xll, yll, cellsize) = match (cols, rows, xll, yll, cellsize) {
+        (Some(cols), Some(rows), Some(xll), Some(yll), Some(cellsize)) => (cols, rows, xll, yll, cellsize),
+        _ => { return Err(String::from("ASCII grid header must contain ncols, nrows, xllcorner, yllcorner & cellsize")); }
+    };
+
+    let header = Header {
+        origin_x: if xll.1 { xll.0 - cellsize / 2.0 } else { xll.0 },
+        origin_y: (if yll.1 { yll.0 - cellsize / 2.0 } else { yll.0 }) + cellsize * rows as f64,
+        scale_x: cellsize,
+        scale_y: -cellsize,
+        cols: cols,
+        rows: rows
+    };
+
+    let window = match header.window(extent) {
+        None => { return Ok(false); },
+        Some(window) => window
+    };
+
+    let mut output = Bands::new(header, window.clone(), band, emit);
+
+    // Values are read as a stream of tokens as rows are not guaranteed to be on a single line
+    let mut index: usize = 0;
+    let mut line = first;
+    while let Some(current) = line {
+        for token in current.split_whitespace() {
+            let col = index % cols;
+            let row = index / cols;
+            index += 1;
+
+            if row >= window.row_end {
+                break;
+            } else if !window.contains(col, row) {
+                continue;
+            }
+
+            let value = match token.parse::<f64>() {
+                Ok(value) => value,
+                Err(_) => { return Err(format!("Invalid ASCII grid value: {}", token)); }
+            };
+
+            if Some(value) == nodata {
+                output.push(std::f32::NAN)?;
+            } else {
+                output.push(value as f32)?;
+            }
+        }
+
+        if index >= window.row_end * cols {
+            break;
+        }
+
+        line = match lines.next() {
+            None => None,
+            Some(Err(err)) => { return Err(err.to_string()); },
+            Some(Ok(line)) => Some(line)
+        };
+    }
+
+    if !output.finish()? {
+        return Err(String::from("ASCII grid contains fewer values than ncols * nrows"));
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: &str = "ncols 4
+nrows 3
+xllcorner 0.0
+yllcorner 0.0
+cellsize 1.0
+NODATA_value -9999
+0 1 2 3
+4 5 6 -9999
+8 9
+10 11
+";
+
+    #[test]
+    fn test_parse() {
+        let raster = parse(GRID.as_bytes(), None).unwrap().unwrap();
+
+        assert_eq!(raster.origin_x, 0.0);
+        assert_eq!(raster.origin_y, 3.0);
+        assert_eq!(raster.cols, 4);
+        assert_eq!(raster.rows, 3);
+        assert_eq!(raster.get(0, 0), Some(0.0));
+        assert_eq!(raster.get(3, 1), None);
+        assert_eq!(raster.get(3, 2), Some(11.0));
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let raster = parse(GRID.as_bytes(), Some(&Extent {
+            minx: 1.2,
+            miny: 0.5,
+            maxx: 2.5,
+            maxy: 1.5
+        })).unwrap().unwrap();
+
+        assert_eq!(raster.origin_x, 1.0);
+        assert_eq!(raster.origin_y, 2.0);
+        assert_eq!(raster.cols, 2);
+        assert_eq!(raster.rows, 2);
+        assert_eq!(raster.values, vec![5.0, 6.0, 9.0, 10.0]);
+
+        assert_eq!(parse(GRID.as_bytes(), Some(&Extent {
+            minx: 10.0,
+            miny: 10.0,
+            maxx: 12.0,
+            maxy: 12.0
+        })).unwrap(), None);
+    }
+
+    #[test]
+    fn test_bands() {
+        let mut bands: Vec<Raster> = Vec::new();
+
+        assert_eq!(bands_of(GRID, 2, &mut bands), Ok(true));
+        assert_eq!(bands.len(), 2);
+
+        assert_eq!((bands[0].origin_y, bands[0].rows), (3.0, 2));
+        assert_eq!(bands[0].values.len(), 8);
+        assert_eq!((bands[1].origin_y, bands[1].rows), (1.0, 1));
+        assert_eq!(bands[1].values, vec![8.0, 9.0, 10.0, 11.0]);
+
+        // Bands together hold the same cells as the whole grid
+        let values: Vec<f32> = bands.iter().flat_map(|band| band.values.iter().cloned()).collect();
+        assert_eq!(values.len(), parse(GRID.as_bytes(), None).unwrap().unwrap().values.len());
+    }
+
+    fn parse(input: impl BufRead, extent: Option<&Extent>) -> Result<Option<Raster>, String> {
+        let mut raster: Option<Raster> = None;
+
+        bands(input, extent, &|_| std::usize::MAX, &mut |band| {
+            raster = Some(band);
+            Ok(())
+        })?;
+
+        Ok(raster)
+    }
+
+    fn bands_of(grid: &str, rows: usize, output: &mut Vec<Raster>) -> Result<bool, String> {
+        bands(grid.as_bytes(), None, &|_| rows, &mut |band| {
+            output.push(band);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_parse_center() {
+        let raster = parse("ncols 1
+nrows 1
+xllcenter 0.5
+yllcenter 0.5
+cellsize 1.0
+7
+".as_bytes(), None).unwrap().unwrap();
+
+        assert_eq!(raster.origin_x, 0.0);
+        assert_eq!(raster.origin_y, 1.0);
+        assert_eq!(raster.get(0, 0), Some(7.0));
+
+        assert_eq!(parse("ncols 1\n1\n".as_bytes(), None), Err(String::from("ASCII grid header must contain ncols, nrows, xllcorner, yllcorner & cellsize")));
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::BufReader;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+use crate::grid::Extent;
+use super::{Raster, Header, Bands};
+
+///
+/// Read a single band, north-up GeoTIFF a band of the given number of rows at a time,
+/// only retaining the cells within the given extent. False is returned if the GeoTIFF does not
+/// intersect the extent
+///
+/// The georeferencing of the raster is determined from the ModelPixelScale &
+/// ModelTiepoint tags, and NODATA from the GDAL_NODATA tag. Only stripped
+/// (non-tiled) GeoTIFFs are supported
+///
+pub fn read(path: &str, extent: Option<&Extent>, band: &dyn Fn(&Header) -> usize, emit: &mut dyn FnMut(Raster) -> Result<(), String>) -> Result<bool, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => { return Err(format!("Unable to open {}: {}", path, err)); }
+    };
+
+    let mut decoder = match Decoder::new(BufReader::new(file)) {
+        Ok(decoder) => decoder.with_limits(Limits::unlimited()),
+        Err(err) => { return Err(format!("Invalid GeoTIFF {}: {}", path, err)); }
+    };
+
+    let (cols, rows) = match decoder.dimensions() {
+        Ok(dimensions) => (dimensions.0 as usize, dimensions.1 as usize),
+        Err(err) => { return Err(format!("Invalid GeoTIFF {}: {}", path, err)); }
+    };
+
+    let scale = match decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag) {
+        Ok(scale) if scale.len() >= 2 => scale,
+        _ => { return Err(format!("GeoTIFF {} is missing ModelPixelScale tag", path)); }
+    };
+
+    // [raster i, raster j, raster k, model x, model y, model z]
+    let tiepoint = match decoder.get_tag_f64_vec(Tag::ModelTiepointTag) {
+        Ok(tiepoint) if tiepoint.len() >= 6 => tiepoint,
+        _ => { return Err(format!("GeoTIFF {} is missing ModelTiepoint tag", path)); }
+    };
+
+    let nodata: Option<f64> = match decoder.get_tag_ascii_string(Tag::GdalNodata) {
+        Ok(nodata) => nodata.trim_matches(char::from(0)).trim().parse::<f64>().ok(),
+        Err(_) => None
+    };
+
+    let rows_per_strip = decoder.get_tag_u32(Tag::RowsPerStrip).unwrap_or(rows as u32) as usize;
+
+    let header = Header {
+        origin_x: tiepoint[3] - tiepoint[0] * scale[0],
+        origin_y: tiepoint[4] + tiepoint[1] * scale[1],
+        scale_x: scale[0],
+        scale_y: -scale[1],
+        cols: cols,
+        rows: rows
+    };
+
+    let window = match header.window(extent) {
+        None => { return Ok(false); },
+        Some(window) => window
+    };
+
+    let mut output = Bands::new(header, window.clone(), band, emit);
+
+    let strips = (window.row_end + rows_per_strip - 1) / rows_per_strip;
+    for strip in 0..strips {
+        let strip_values: Vec<f64> = match decoder.read_strip() {
+            Ok(DecodingResult::U8(v)) => v.into_iter().map(|v| v as f64).collect(),
+            Ok(DecodingResult::U16(v)) => v.into_iter().map(|v| v as f64).collect(),
+            Ok(DecodingResult::U32(v)) => v.into_iter().map(|v| v as f64).collect(),
+            Ok(DecodingResult::U64(v)) => v.into_iter().map(|v| v as f64).collect(),
+            Ok(DecodingResult::F32(v)) => v.into_iter().map(|v| v as f64).collect(),
+            Ok(DecodingResult::F64(v)) => v,
+            Err(err) => { return Err(format!("Failed to read GeoTIFF {}: {}", path, err)); }
+        };
+
+        for (i, value) in strip_values.iter().enumerate() {
+            let row = strip * rows_per_strip + i / cols;
+            let col = i % cols;
+
+            if !window.contains(col, row) {
+                continue;
+            }
+
+            // NODATA is stored as text, so may not exactly match a float value
+            let is_nodata = match nodata {
+                Some(nodata) => (value - nodata).abs() <= nodata.abs() * 1e-6,
+                None => false
+            };
+
+            if is_nodata || value.is_nan() {
+                output.push(std::f32::NAN)?;
+            } else {
+                output.push(*value as f32)?;
+            }
+        }
+    }
+
+    if !output.finish()? {
+        return Err(format!("GeoTIFF {} must be a single band raster", path));
+    }
+
+    Ok(true)
+}
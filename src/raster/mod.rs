@@ -0,0 +1,564 @@
+use crate::grid::Extent;
+
+pub mod asc;
+pub mod geotiff;
+pub mod wkb;
+
+///
+/// A single band, north-up raster held in memory
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Raster {
+    /// X coordinate of the upper left corner of the raster
+    pub origin_x: f64,
+
+    /// Y coordinate of the upper left corner of the raster
+    pub origin_y: f64,
+
+    /// Width of a cell
+    pub scale_x: f64,
+
+    /// Height of a cell (negative as rows run north to south)
+    pub scale_y: f64,
+
+    pub cols: usize,
+    pub rows: usize,
+
+    /// Row major cell values, with NODATA cells stored as NaN
+    pub values: Vec<f32>
+}
+
+///
+/// The geometry of a raster file, used to determine which
+/// rows & columns of the file should be read
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Header {
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub scale_x: f64,
+    pub scale_y: f64,
+    pub cols: usize,
+    pub rows: usize
+}
+
+///
+/// Inclusive start, exclusive end range of rows & columns to read from a raster file
+///
+#[derive(Debug, PartialEq, Clone)]
+pub struct Window {
+    pub col_start: usize,
+    pub col_end: usize,
+    pub row_start: usize,
+    pub row_end: usize
+}
+
+impl Window {
+    pub fn cols(&self) -> usize {
+        self.col_end - self.col_start
+    }
+
+    pub fn rows(&self) -> usize {
+        self.row_end - self.row_start
+    }
+
+    pub fn contains(&self, col: usize, row: usize) -> bool {
+        col >= self.col_start && col < self.col_end && row >= self.row_start && row < self.row_end
+    }
+}
+
+impl Header {
+    ///
+    /// Return the window of the raster that intersects the given extent,
+    /// the whole raster if no extent is given, or None if they do not intersect
+    ///
+    pub fn window(&self, extent: Option<&Extent>) -> Option<Window> {
+        let extent = match extent {
+            None => {
+                return Some(Window {
+                    col_start: 0,
+                    col_end: self.cols,
+                    row_start: 0,
+                    row_end: self.rows
+                });
+            },
+            Some(extent) => extent
+        };
+
+        let col_start = ((extent.minx - self.origin_x) / self.scale_x).floor().max(0.0);
+        let col_end = ((extent.maxx - self.origin_x) / self.scale_x).ceil().min(self.cols as f64);
+        let row_start = ((extent.maxy - self.origin_y) / self.scale_y).floor().max(0.0);
+        let row_end = ((extent.miny - self.origin_y) / self.scale_y).ceil().min(self.rows as f64);
+
+        if col_start >= col_end || row_start >= row_end {
+            return None;
+        }
+
+        Some(Window {
+            col_start: col_start as usize,
+            col_end: col_end as usize,
+            row_start: row_start as usize,
+            row_end: row_end as usize
+        })
+    }
+}
+
+///
+/// Collects the cells of the window of a raster file as they are read, passing them on
+/// as a raster of at most a given number of rows at a time
+///
+pub struct Bands<'a> {
+    header: Header,
+    window: Window,
+
+    /// Maximum number of rows of each band
+    rows: usize,
+
+    /// Number of rows of the window already passed on
+    row: usize,
+
+    values: Vec<f32>,
+    emit: &'a mut dyn FnMut(Raster) -> Result<(), String>
+}
+
+impl<'a> Bands<'a> {
+    pub fn new(header: Header, window: Window, band: &dyn Fn(&Header) -> usize, emit: &'a mut dyn FnMut(Raster) -> Result<(), String>) -> Self {
+        let rows = band(&header).max(1).min(window.rows());
+
+        Bands {
+            values: Vec::with_capacity(window.cols() * rows),
+            header: header,
+            window: window,
+            rows: rows,
+            row: 0,
+            emit: emit
+        }
+    }
+
+    ///
+    /// Add the value of the next cell of the window, in row major order
+    ///
+    pub fn push(&mut self, value: f32) -> Result<(), String> {
+        self.values.push(value);
+
+        if self.values.len() == self.window.cols() * self.rows {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        if self.values.is_empty() {
+            return Ok(());
+        }
+
+        let rows = self.values.len() / self.window.cols();
+        let values = std::mem::replace(&mut self.values, Vec::with_capacity(self.window.cols() * self.rows));
+
+        (self.emit)(Raster {
+            origin_x: self.header.origin_x + self.header.scale_x * self.window.col_start as f64,
+            origin_y: self.header.origin_y + self.header.scale_y * (self.window.row_start + self.row) as f64,
+            scale_x: self.header.scale_x,
+            scale_y: self.header.scale_y,
+            cols: self.window.cols(),
+            rows: rows,
+            values: values
+        })?;
+
+        self.row += rows;
+
+        Ok(())
+    }
+
+    ///
+    /// Pass on the last band, returning whether every cell of the window was read
+    ///
+    pub fn finish(mut self) -> Result<bool, String> {
+        let complete = self.values.len() % self.window.cols() == 0;
+
+        self.flush()?;
+
+        Ok(complete && self.row == self.window.rows())
+    }
+}
+
+impl Raster {
+    ///
+    /// Open an ESRI ASCII grid (.asc) or GeoTIFF (.tif) file, reading only the
+    /// portion that intersects the given extent. None is returned if the file
+    /// does not intersect the extent
+    ///
+    pub fn open(path: &str, extent: Option<&Extent>) -> Result<Option<Self>, String> {
+        let mut raster: Option<Raster> = None;
+
+        Raster::stream(path, extent, &|_| std::usize::MAX, &mut |band| {
+            raster = Some(band);
+            Ok(())
+        })?;
+
+        Ok(raster)
+    }
+
+    ///
+    /// Read the portion of an ESRI ASCII grid (.asc) or GeoTIFF (.tif) file that intersects the
+    /// given extent a band of rows at a time, the number of rows being given for the header of the
+    /// file, so that only a single band of a global raster is held in memory. False is returned if
+    /// the file does not intersect the extent
+    ///
+    pub fn stream(path: &str, extent: Option<&Extent>, band: &dyn Fn(&Header) -> usize, emit: &mut dyn FnMut(Raster) -> Result<(), String>) -> Result<bool, String> {
+        let lower = path.to_lowercase();
+
+        if lower.ends_with(".asc") {
+            asc::read(path, extent, band, emit)
+        } else if lower.ends_with(".tif") || lower.ends_with(".tiff") {
+            geotiff::read(path, extent, band, emit)
+        } else {
+            Err(format!("{} must be an ESRI ASCII grid (.asc) or GeoTIFF (.tif)", path))
+        }
+    }
+
+    ///
+    /// Return the value of a cell, None if the cell is NODATA
+    ///
+    pub fn get(&self, col: usize, row: usize) -> Option<f64> {
+        let value = self.values[row * self.cols + col];
+
+        if value.is_nan() {
+            None
+        } else {
+            Some(value as f64)
+        }
+    }
+
+    ///
+    /// Return the coordinates of the centre of a cell
+    ///
+    pub fn centre(&self, col: usize, row: usize) -> (f64, f64) {
+        (
+            self.origin_x + self.scale_x * (col as f64 + 0.5),
+            self.origin_y + self.scale_y * (row as f64 + 0.5)
+        )
+    }
+
+    ///
+    /// Return the bounding rectangle of a cell
+    ///
+    pub fn cell(&self, col: usize, row: usize) -> geo::Rect<f64> {
+        geo::Rect::new(
+            geo::Coordinate {
+                x: self.origin_x + self.scale_x * col as f64,
+                y: self.origin_y + self.scale_y * (row + 1) as f64
+            },
+            geo::Coordinate {
+                x: self.origin_x + self.scale_x * (col + 1) as f64,
+                y: self.origin_y + self.scale_y * row as f64
+            }
+        )
+    }
+
+    pub fn extent(&self) -> Extent {
+        Extent {
+            minx: self.origin_x,
+            miny: self.origin_y + self.scale_y * self.rows as f64,
+            maxx: self.origin_x + self.scale_x * self.cols as f64,
+            maxy: self.origin_y
+        }
+    }
+
+    ///
+    /// Return whether the centre of each cell falls within the given geometry
+    ///
+    pub fn mask(&self, geom: &geo::MultiPolygon<f64>) -> Vec<bool> {
+        let mut mask = vec![false; self.cols * self.rows];
+
+        for row in 0..self.rows {
+            let y = self.origin_y + self.scale_y * (row as f64 + 0.5);
+            let xs = crossings(geom, y);
+
+            // Cells between each pair of crossings are within the geometry
+            for pair in xs.chunks(2) {
+                if pair.len() < 2 {
+                    continue;
+                }
+
+                let start = ((pair[0] - self.origin_x) / self.scale_x - 0.5).ceil().max(0.0) as usize;
+                let end = ((pair[1] - self.origin_x) / self.scale_x - 0.5).ceil().max(0.0).min(self.cols as f64) as usize;
+
+                for col in start..end {
+                    mask[row * self.cols + col] = true;
+                }
+            }
+        }
+
+        mask
+    }
+
+    ///
+    /// Split the raster into tiles of at most size x size cells, omitting
+    /// tiles which contain only NODATA cells
+    ///
+    pub fn tiles(&self, size: usize) -> Vec<Raster> {
+        let mut tiles = Vec::new();
+
+        for row_start in (0..self.rows).step_by(size) {
+            for col_start in (0..self.cols).step_by(size) {
+                let cols = size.min(self.cols - col_start);
+                let rows = size.min(self.rows - row_start);
+
+                let mut values = Vec::with_capacity(cols * rows);
+                for row in row_start..row_start + rows {
+                    let start = row * self.cols + col_start;
+                    values.extend_from_slice(&self.values[start..start + cols]);
+                }
+
+                if values.iter().all(|value| value.is_nan()) {
+                    continue;
+                }
+
+                tiles.push(Raster {
+                    origin_x: self.origin_x + self.scale_x * col_start as f64,
+                    origin_y: self.origin_y + self.scale_y * row_start as f64,
+                    scale_x: self.scale_x,
+                    scale_y: self.scale_y,
+                    cols: cols,
+                    rows: rows,
+                    values: values
+                });
+            }
+        }
+
+        tiles
+    }
+
+    ///
+    /// Merge rasters sharing the same cell size & grid alignment, such as the
+    /// tiles of a global product, into a single raster
+    ///
+    pub fn merge(rasters: Vec<Raster>) -> Result<Raster, String> {
+        let first = match rasters.first() {
+            None => { return Err(String::from("No rasters to merge")); },
+            Some(first) => first.clone()
+        };
+
+        if rasters.len() == 1 {
+            return Ok(first);
+        }
+
+        let mut extent = first.extent();
+        for raster in rasters.iter() {
+            if (raster.scale_x - first.scale_x).abs() > std::f64::EPSILON || (raster.scale_y - first.scale_y).abs() > std::f64::EPSILON {
+                return Err(String::from("Rasters must have the same cell size to be merged"));
+            }
+
+            let raster_extent = raster.extent();
+            extent.minx = extent.minx.min(raster_extent.minx);
+            extent.miny = extent.miny.min(raster_extent.miny);
+            extent.maxx = extent.maxx.max(raster_extent.maxx);
+            extent.maxy = extent.maxy.max(raster_extent.maxy);
+        }
+
+        let cols = ((extent.maxx - extent.minx) / first.scale_x).round() as usize;
+        let rows = ((extent.miny - extent.maxy) / first.scale_y).round() as usize;
+
+        let mut merged = Raster {
+            origin_x: extent.minx,
+            origin_y: extent.maxy,
+            scale_x: first.scale_x,
+            scale_y: first.scale_y,
+            cols: cols,
+            rows: rows,
+            values: vec![std::f32::NAN; cols * rows]
+        };
+
+        for raster in rasters {
+            let col_offset = ((raster.origin_x - merged.origin_x) / merged.scale_x).round() as usize;
+            let row_offset = ((raster.origin_y - merged.origin_y) / merged.scale_y).round() as usize;
+
+            for row in 0..raster.rows {
+                for col in 0..raster.cols {
+                    let value = raster.values[row * raster.cols + col];
+
+                    if !value.is_nan() {
+                        merged.values[(row + row_offset) * cols + col + col_offset] = value;
+                    }
+                }
+            }
+        }
+
+        Ok(merged)
+    }
+}
+
+///
+/// Return the sorted x coordinates at which a horizontal line at y crosses the rings of
+/// a geometry. Pairs of crossings bound the portions of the line within the geometry
+///
+pub fn crossings(geom: &geo::MultiPolygon<f64>, y: f64) -> Vec<f64> {
+    let mut xs: Vec<f64> = Vec::new();
+
+    for poly in geom.0.iter() {
+        for ring in std::iter::once(poly.exterior()).chain(poly.interiors().iter()) {
+            for line in ring.lines() {
+                if (line.start.y <= y) != (line.end.y <= y) {
+                    xs.push(line.start.x + (y - line.start.y) / (line.end.y - line.start.y) * (line.end.x - line.start.x));
+                }
+            }
+        }
+    }
+
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    xs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raster(origin_x: f64, origin_y: f64, values: Vec<f32>) -> Raster {
+        Raster {
+            origin_x: origin_x,
+            origin_y: origin_y,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: 2,
+            rows: 2,
+            values: values
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let header = Header {
+            origin_x: -180.0,
+            origin_y: 90.0,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: 360,
+            rows: 180
+        };
+
+        assert_eq!(header.window(None), Some(Window {
+            col_start: 0,
+            col_end: 360,
+            row_start: 0,
+            row_end: 180
+        }));
+
+        assert_eq!(header.window(Some(&Extent {
+            minx: -62.7,
+            miny: -27.6,
+            maxx: -54.2,
+            maxy: -19.3
+        })), Some(Window {
+            col_start: 117,
+            col_end: 126,
+            row_start: 109,
+            row_end: 118
+        }));
+
+        assert_eq!(header.window(Some(&Extent {
+            minx: 190.0,
+            miny: 0.0,
+            maxx: 200.0,
+            maxy: 10.0
+        })), None);
+    }
+
+    #[test]
+    fn test_raster() {
+        let raster = raster(0.0, 2.0, vec![1.0, std::f32::NAN, 3.0, 4.0]);
+
+        assert_eq!(raster.get(0, 0), Some(1.0));
+        assert_eq!(raster.get(1, 0), None);
+        assert_eq!(raster.get(1, 1), Some(4.0));
+        assert_eq!(raster.centre(1, 0), (1.5, 1.5));
+        assert_eq!(raster.extent(), Extent {
+            minx: 0.0,
+            miny: 0.0,
+            maxx: 2.0,
+            maxy: 2.0
+        });
+    }
+
+    #[test]
+    fn test_mask() {
+        let raster = Raster {
+            origin_x: 0.0,
+            origin_y: 4.0,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: 4,
+            rows: 4,
+            values: vec![1.0; 16]
+        };
+
+        let geom = geo::MultiPolygon(vec![geo::Polygon::new(
+            vec![(0.2, 0.2), (3.8, 0.2), (3.8, 3.8), (0.2, 3.8), (0.2, 0.2)].into(),
+            vec![vec![(1.2, 1.2), (2.8, 1.2), (2.8, 2.8), (1.2, 2.8), (1.2, 1.2)].into()]
+        )]);
+
+        assert_eq!(raster.mask(&geom), vec![
+            true, true, true, true,
+            true, false, false, true,
+            true, false, false, true,
+            true, true, true, true
+        ]);
+
+        assert_eq!(crossings(&geom, 2.5), vec![0.2, 1.2, 2.8, 3.8]);
+        assert_eq!(crossings(&geom, 5.0), Vec::<f64>::new());
+    }
+
+    #[test]
+    fn test_tiles() {
+        let raster = Raster {
+            origin_x: 0.0,
+            origin_y: 3.0,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: 3,
+            rows: 3,
+            values: vec![
+                1.0, 2.0, std::f32::NAN,
+                4.0, 5.0, std::f32::NAN,
+                7.0, 8.0, 9.0
+            ]
+        };
+
+        let tiles = raster.tiles(2);
+        assert_eq!(tiles.len(), 3);
+        assert_eq!(tiles[0], raster_with(0.0, 3.0, 2, 2, vec![1.0, 2.0, 4.0, 5.0]));
+        assert_eq!(tiles[1], raster_with(0.0, 1.0, 2, 1, vec![7.0, 8.0]));
+        assert_eq!(tiles[2], raster_with(2.0, 1.0, 1, 1, vec![9.0]));
+    }
+
+    fn raster_with(origin_x: f64, origin_y: f64, cols: usize, rows: usize, values: Vec<f32>) -> Raster {
+        Raster {
+            origin_x: origin_x,
+            origin_y: origin_y,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: cols,
+            rows: rows,
+            values: values
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let merged = Raster::merge(vec![
+            raster(0.0, 2.0, vec![1.0, 2.0, 3.0, 4.0]),
+            raster(2.0, 0.0, vec![5.0, 6.0, 7.0, 8.0])
+        ]).unwrap();
+
+        assert_eq!(merged.origin_x, 0.0);
+        assert_eq!(merged.origin_y, 2.0);
+        assert_eq!(merged.cols, 4);
+        assert_eq!(merged.rows, 4);
+        assert_eq!(merged.get(0, 0), Some(1.0));
+        assert_eq!(merged.get(1, 1), Some(4.0));
+        assert_eq!(merged.get(2, 0), None);
+        assert_eq!(merged.get(2, 2), Some(5.0));
+        assert_eq!(merged.get(3, 3), Some(8.0));
+    }
+}
@@ -0,0 +1,79 @@
+use super::Raster;
+
+/// Value used to store NODATA cells in PostGIS
+pub const NODATA: f32 = -9999.0;
+
+/// PostGIS raster 32 bit float pixel type
+const PT_32BF: u8 = 10;
+
+/// PostGIS raster band flag indicating the band has a NODATA value
+const BANDTYPE_FLAG_HASNODATA: u8 = 0x40;
+
+impl Raster {
+    ///
+    /// Return the raster as hex encoded PostGIS raster WKB in EPSG:4326,
+    /// which can be cast directly to a PostGIS RASTER
+    ///
+    pub fn to_hex_wkb(&self) -> String {
+        let mut wkb: Vec<u8> = Vec::with_capacity(61 + 5 + self.values.len() * 4);
+
+        // Little endian, version 0, single band
+        wkb.push(1);
+        wkb.extend_from_slice(&0u16.to_le_bytes());
+        wkb.extend_from_slice(&1u16.to_le_bytes());
+
+        wkb.extend_from_slice(&self.scale_x.to_le_bytes());
+        wkb.extend_from_slice(&self.scale_y.to_le_bytes());
+        wkb.extend_from_slice(&self.origin_x.to_le_bytes());
+        wkb.extend_from_slice(&self.origin_y.to_le_bytes());
+
+        // No skew
+        wkb.extend_from_slice(&0f64.to_le_bytes());
+        wkb.extend_from_slice(&0f64.to_le_bytes());
+
+        wkb.extend_from_slice(&4326i32.to_le_bytes());
+        wkb.extend_from_slice(&(self.cols as u16).to_le_bytes());
+        wkb.extend_from_slice(&(self.rows as u16).to_le_bytes());
+
+        wkb.push(PT_32BF | BANDTYPE_FLAG_HASNODATA);
+        wkb.extend_from_slice(&NODATA.to_le_bytes());
+
+        for value in self.values.iter() {
+            if value.is_nan() {
+                wkb.extend_from_slice(&NODATA.to_le_bytes());
+            } else {
+                wkb.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        wkb.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_hex_wkb() {
+        let raster = Raster {
+            origin_x: 0.0,
+            origin_y: 1.0,
+            scale_x: 1.0,
+            scale_y: -1.0,
+            cols: 2,
+            rows: 1,
+            values: vec![1.0, std::f32::NAN]
+        };
+
+        assert_eq!(raster.to_hex_wkb(), String::from("")
+            + "01" + "0000" + "0100"
+            + "000000000000F03F" + "000000000000F0BF"
+            + "0000000000000000" + "000000000000F03F"
+            + "0000000000000000" + "0000000000000000"
+            + "E6100000" + "0200" + "0100"
+            + "4A" + "003C1CC6"
+            + "0000803F" + "003C1CC6"
+        );
+    }
+}
@@ -34,6 +34,9 @@ pub fn main(pool: r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::
                 .service(web::resource("network/{z}/{x}/{y}")
                     .route(web::get().to(mvt_network_get))
                 )
+                .service(web::resource("diff/{z}/{x}/{y}")
+                    .route(web::get().to(mvt_diff_get))
+                )
             )
             .service(
                 actix_files::Files::new("/", String::from("./web/dist/"))
@@ -66,8 +69,21 @@ fn map_get(
         }
     };
 
+    // The diff layer is only available once the diff subcommand has been run against the country
+    let diff = match db.get().unwrap().query("
+        SELECT
+            to_regclass($1) IS NOT NULL
+    ", &[&format!("country_{}.diff", &iso.0)]) {
+        Err(err) => panic!(err.to_string()),
+        Ok(rows) => {
+            let diff: bool = rows.get(0).unwrap().get(0);
+            diff
+        }
+    };
+
     Json(json!({
         "bounds": extent,
+        "diff": diff,
         "token": token.as_str()
     }))
 }
@@ -129,3 +145,32 @@ fn mvt_coverage_get(
        .content_length(tile.len() as u64)
        .body(tile)
 }
+
+fn mvt_diff_get(
+    db: web::Data<r2d2::Pool<r2d2_postgres::PostgresConnectionManager<postgres::NoTls>>>,
+    iso: web::Data<Country>,
+    path: web::Path<(u8, u32, u32)>
+) -> HttpResponse {
+    let z = path.0;
+    let x = path.1;
+    let y = path.2;
+
+    let tile = match mvt::diff(&db, &iso.0, z, x, y) {
+        Ok(tile) => tile,
+        Err(err) => {
+            println!("{}", err);
+
+            let body: String = err.to_string();
+
+            return HttpResponse::build(actix_web::http::StatusCode::NOT_FOUND)
+               .content_type("text/plain")
+               .content_length(body.len() as u64)
+               .body(body);
+        }
+    };
+
+    HttpResponse::build(actix_web::http::StatusCode::OK)
+       .content_type("application/x-protobuf")
+       .content_length(tile.len() as u64)
+       .body(tile)
+}